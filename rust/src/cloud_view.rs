@@ -0,0 +1,138 @@
+use std::borrow::Cow;
+
+use crate::pointcloud::{NormalStatus, PointCloud};
+
+/// Subset of the points of a cloud, given by their (original) indices into the cloud.
+///
+/// Views don't copy any point data. They can be narrowed down step by step, e.g. overlap ->
+/// subsample -> reject, and each step refers to the positions within the current view. The original
+/// indices are always available through `indices()`.
+#[derive(Clone)]
+pub struct CloudView<'a> {
+    cloud: &'a PointCloud,
+    idx: Cow<'a, [usize]>,
+}
+
+impl<'a> CloudView<'a> {
+    // View on all points of the cloud
+    pub fn new(cloud: &'a PointCloud) -> CloudView<'a> {
+        CloudView { cloud, idx: Cow::Owned((0..cloud.point_amount()).collect()) }
+    }
+
+    // View on the points with the given original indices
+    pub fn from_indices(cloud: &'a PointCloud, idx: impl Into<Cow<'a, [usize]>>) -> CloudView<'a> {
+        let idx = idx.into();
+        assert!(idx.iter().all(|i| *i < cloud.point_amount()), "Index out of bounds of the point cloud");
+        CloudView { cloud, idx }
+    }
+
+    pub fn cloud(&self) -> &'a PointCloud {
+        self.cloud
+    }
+
+    pub fn indices(&self) -> &[usize] {
+        &self.idx
+    }
+
+    pub fn len(&self) -> usize {
+        self.idx.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.idx.is_empty()
+    }
+
+    // Original index of the point at position i of the view
+    pub fn original_idx(&self, i: usize) -> usize {
+        self.idx[i]
+    }
+
+    pub fn point(&self, i: usize) -> [f64; 3] {
+        let p = self.cloud.points();
+        let j = self.idx[i];
+        [p[[j, 0]], p[[j, 1]], p[[j, 2]]]
+    }
+
+    pub fn normal(&self, i: usize) -> [f64; 3] {
+        let n = self.cloud.normals();
+        let j = self.idx[i];
+        [n[[j, 0]], n[[j, 1]], n[[j, 2]]]
+    }
+
+    pub fn planarity(&self, i: usize) -> f64 {
+        self.cloud.planarity()[self.idx[i]]
+    }
+
+    pub fn covariance(&self, i: usize) -> [[f64; 3]; 3] {
+        self.cloud.covariances()[self.idx[i]]
+    }
+
+    pub fn color(&self, i: usize) -> [f64; 3] {
+        let c = self.cloud.colors();
+        let j = self.idx[i];
+        [c[[j, 0]], c[[j, 1]], c[[j, 2]]]
+    }
+
+    // Mean of the RGB values
+    pub fn intensity(&self, i: usize) -> f64 {
+        self.color(i).iter().sum::<f64>() / 3.0
+    }
+
+    pub fn color_gradient(&self, i: usize) -> [f64; 3] {
+        let d = self.cloud.color_gradients();
+        let j = self.idx[i];
+        [d[[j, 0]], d[[j, 1]], d[[j, 2]]]
+    }
+
+    pub fn normal_status(&self, i: usize) -> NormalStatus {
+        self.cloud.normal_status()[self.idx[i]]
+    }
+
+    pub fn points(&self) -> impl Iterator<Item=[f64; 3]> + '_ {
+        (0..self.len()).map(|i| self.point(i))
+    }
+
+    pub fn normals(&self) -> impl Iterator<Item=[f64; 3]> + '_ {
+        (0..self.len()).map(|i| self.normal(i))
+    }
+
+    // Narrows the view down to the given positions within this view
+    pub fn subset(&self, positions: &[usize]) -> CloudView<'a> {
+        CloudView {
+            cloud: self.cloud,
+            idx: Cow::Owned(positions.iter().map(|i| self.idx[*i]).collect()),
+        }
+    }
+
+    // Narrows the view down to the positions for which keep returns true
+    pub fn filter(&self, mut keep: impl FnMut(usize) -> bool) -> CloudView<'a> {
+        let positions: Vec<usize> = (0..self.len()).filter(|i| keep(*i)).collect();
+        self.subset(&positions)
+    }
+
+    // Copies the viewed points and their attributes into a new point cloud
+    pub fn to_cloud(&self) -> PointCloud {
+        PointCloud::select_from_cloud(self.cloud, &self.idx)
+    }
+}
+
+
+#[cfg(test)]
+mod cloud_view_test {
+    use crate::pointcloud::PointCloud;
+
+    #[test]
+    fn chained_views_map_to_original_indices() {
+        let cloud = PointCloud::new((0..30).map(|v| v as f64).collect());
+        let view = cloud.view().filter(|i| i % 2 == 0);
+        assert_eq!(view.indices(), &[0, 2, 4, 6, 8]);
+
+        let view = view.subset(&[1, 3, 4]);
+        assert_eq!(view.indices(), &[2, 6, 8]);
+        assert_eq!(view.point(1), [18.0, 19.0, 20.0]);
+
+        let copy = view.to_cloud();
+        assert_eq!(copy.point_amount(), 3);
+        assert_eq!(copy.selection_idx(), &[0, 1, 2]);
+    }
+}
@@ -1,68 +1,525 @@
-use std::borrow::Borrow;
-use std::iter::Filter;
-use std::num::FpCategory::Nan;
-use std::time::Instant;
-
-use itertools::izip;
-use ndarray::{Array, Array1, Axis, Ix1};
-
-use crate::permutation::{PermuteArray, SortArray};
-use crate::pointcloud::{CloudToCloudDist, PointCloud};
-
-fn get_median(data: &Array1<f64>) -> f64 {
-    let data_copy = data.clone();
-    let perm = data_copy.sort_axis_by(Axis(0), |i, j| data_copy[[i]] > data_copy[[j]]);
-    let sorted = data_copy.permute_axis(Axis(0), &perm);
-    let len = data.len();
-    return if len % 2 == 0 {
-        // If the length of the array is even, take the average of the two middle elements
-        (sorted[len / 2] + sorted[(len / 2) - 1]) / 2.0
-    } else {
-        // If the length of the array is odd, take the middle element
-        sorted[len / 2]
-    };
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use ndarray::{Array1, Axis};
+
+use crate::cloud_view::CloudView;
+use crate::nearest_neighbor::{knn_search, NNRes};
+use crate::stats::{median, sigma_mad};
+
+/// Correspondences of one iteration, position i of `fixed` corresponds to position i of `moved`.
+#[derive(Clone)]
+pub struct Correspondences<'a> {
+    pub fixed: CloudView<'a>,
+    pub moved: CloudView<'a>,
+    /// Distances w.r.t. the error metric, e.g. point-to-plane w.r.t. the normals of the fixed points.
+    pub dist: Array1<f64>,
+    /// Whether the distances are signed, otherwise they are non-negative (e.g. point-to-point).
+    pub signed: bool,
+    /// Iteration of the registration, starting at 0.
+    pub iteration: usize,
+}
+
+impl<'a> Correspondences<'a> {
+    pub fn len(&self) -> usize {
+        self.fixed.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fixed.is_empty()
+    }
+
+    pub fn subset(&self, positions: &[usize]) -> Correspondences<'a> {
+        Correspondences {
+            fixed: self.fixed.subset(positions),
+            moved: self.moved.subset(positions),
+            dist: self.dist.select(Axis(0), positions),
+            signed: self.signed,
+            iteration: self.iteration,
+        }
+    }
+}
+
+/// Stage of the correspondence rejection pipeline.
+pub trait CorrespondenceRejector: Debug + Sync {
+    /// Short name of the stage, e.g. in reports and diagnostics.
+    fn name(&self) -> &str;
+
+    /// Returns the positions of the correspondences which are kept.
+    fn keep(&self, corr: &Correspondences) -> Vec<usize>;
+
+    /// Whether the normals (and planarity) of the fixed points have to be estimated before.
+    fn needs_fixed_normals(&self) -> bool {
+        false
+    }
+
+    /// Whether the normals of the moved points have to be estimated before.
+    fn needs_moved_normals(&self) -> bool {
+        false
+    }
+}
+
+/// Rejects distances which deviate more than `factor` * sigma_mad from their median. Non-negative
+/// distances are only rejected above the median, as the smallest ones are the best matches.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MadRejector {
+    pub factor: f64,
+}
+
+impl CorrespondenceRejector for MadRejector {
+    fn name(&self) -> &str {
+        "mad"
+    }
+
+    fn keep(&self, corr: &Correspondences) -> Vec<usize> {
+        let med = median(&corr.dist);
+        let sigmad = sigma_mad(&corr.dist);
+        if corr.signed {
+            positions_where(corr, |i| f64::abs(corr.dist[i] - med) <= self.factor * sigmad)
+        } else {
+            positions_where(corr, |i| corr.dist[i] - med <= self.factor * sigmad)
+        }
+    }
+}
+
+/// Rejects absolute distances larger than `max_distance`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DistanceRejector {
+    pub max_distance: f64,
+}
+
+impl CorrespondenceRejector for DistanceRejector {
+    fn name(&self) -> &str {
+        "distance"
+    }
+
+    fn keep(&self, corr: &Correspondences) -> Vec<usize> {
+        positions_where(corr, |i| corr.dist[i].abs() <= self.max_distance)
+    }
+}
+
+/// Keeps the fraction `keep_ratio` of the correspondences with the smallest absolute distances.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrimmedRejector {
+    pub keep_ratio: f64,
+}
+
+impl CorrespondenceRejector for TrimmedRejector {
+    fn name(&self) -> &str {
+        "trimmed"
+    }
+
+    fn keep(&self, corr: &Correspondences) -> Vec<usize> {
+        let n = (self.keep_ratio.clamp(0.0, 1.0) * corr.len() as f64).round() as usize;
+        let mut order: Vec<usize> = (0..corr.len()).collect();
+        order.sort_by(|a, b| corr.dist[*a].abs().total_cmp(&corr.dist[*b].abs()));
+        order.truncate(n);
+        order.sort_unstable();
+        order
+    }
+}
+
+/// Rejects fixed points whose planarity is below `min_planarity`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlanarityRejector {
+    pub min_planarity: f64,
+}
+
+impl CorrespondenceRejector for PlanarityRejector {
+    fn name(&self) -> &str {
+        "planarity"
+    }
+
+    fn keep(&self, corr: &Correspondences) -> Vec<usize> {
+        positions_where(corr, |i| corr.fixed.planarity(i) >= self.min_planarity)
+    }
+
+    fn needs_fixed_normals(&self) -> bool {
+        true
+    }
 }
 
-fn get_mad(data: &Array1<f64>, median: f64) -> f64 {
-    let dmed: Array<f64, Ix1> = data.map(|x| f64::abs(x - median));
-    get_median(&dmed)
+/// Rejects correspondences whose normals enclose an angle larger than `max_angle` (in degree).
+#[derive(Clone, Debug, PartialEq)]
+pub struct NormalAngleRejector {
+    pub max_angle: f64,
 }
 
-// Returns valid point indices indices
-pub(crate) fn reject(cloud: &PointCloud, dist: &mut CloudToCloudDist, min_planarity: usize) -> Vec<usize> {
+impl CorrespondenceRejector for NormalAngleRejector {
+    fn name(&self) -> &str {
+        "angle"
+    }
 
-    let med = get_median(dist.dist.borrow());
-    let mad = get_mad(dist.dist.borrow(), med);
-    let sigmad = 1.4826 * mad;
+    fn keep(&self, corr: &Correspondences) -> Vec<usize> {
+        reject_by_normal_angle(&corr.fixed, &corr.moved, self.max_angle.to_radians())
+    }
 
-    assert_eq!(cloud.point_amount(), dist.nn.len());
+    fn needs_fixed_normals(&self) -> bool {
+        true
+    }
 
-    let keep: Vec<usize> = izip!(cloud.planarity().iter(), dist.dist.iter())
-        .enumerate()
-        .filter(|(idx, (p, dist))| {
-            (f64::abs(**dist - med) <= 3.0 * sigmad)//(f64::abs(**dist - med) <= 3.0 * sigmad) && (**p >= min_planarity as f64)
+    fn needs_moved_normals(&self) -> bool {
+        true
+    }
+}
+
+/// Rejects correspondences whose moved point lies on the boundary of the moved cloud, i.e. the
+/// centroid of its `neighbors` nearest neighbors is farther away from it than `factor` times their
+/// mean distance. Boundary points are often matched to points which have no counterpart.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoundaryRejector {
+    pub neighbors: usize,
+    pub factor: f64,
+}
+
+impl CorrespondenceRejector for BoundaryRejector {
+    fn name(&self) -> &str {
+        "boundary"
+    }
+
+    fn keep(&self, corr: &Correspondences) -> Vec<usize> {
+        let all = corr.moved.cloud().view();
+        let nn = knn_search(&all, &corr.moved, self.neighbors + 1);
+        positions_where(corr, |i| {
+            let p = corr.moved.point(i);
+            // Usually the first neighbor is the point itself, but not necessarily for duplicate points
+            let own_idx = corr.moved.original_idx(i);
+            let others: Vec<&NNRes> = nn[i].iter().filter(|n| n.idx != own_idx).take(self.neighbors).collect();
+            if others.is_empty() {
+                return false;
+            }
+            let mut centroid = [0.0; 3];
+            for q in others.iter().map(|n| all.point(n.idx)) {
+                (0..3).for_each(|axis| centroid[axis] += q[axis] / others.len() as f64);
+            }
+            let offset = (0..3).map(|axis| (centroid[axis] - p[axis]).powi(2)).sum::<f64>().sqrt();
+            let mean_dist = others.iter().map(|n| n.distance.sqrt()).sum::<f64>() / others.len() as f64;
+            offset <= self.factor * mean_dist
         })
-        .map(|(idx, _)| {
-            idx
+    }
+}
+
+/// Rejects absolute distances larger than `initial` * `factor`^iteration, but never below `min`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShrinkingDistanceRejector {
+    pub initial: f64,
+    pub factor: f64,
+    pub min: f64,
+}
+
+impl CorrespondenceRejector for ShrinkingDistanceRejector {
+    fn name(&self) -> &str {
+        "shrinking"
+    }
+
+    fn keep(&self, corr: &Correspondences) -> Vec<usize> {
+        let max_distance = (self.initial * self.factor.powi(corr.iteration as i32)).max(self.min);
+        positions_where(corr, |i| corr.dist[i].abs() <= max_distance)
+    }
+}
+
+fn positions_where(corr: &Correspondences, keep: impl Fn(usize) -> bool) -> Vec<usize> {
+    (0..corr.len()).filter(|i| keep(*i)).collect()
+}
+
+/// Number of correspondences before and after a stage of the pipeline.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StageReport {
+    pub stage: String,
+    pub before: usize,
+    pub after: usize,
+    /// Positions of the rejected correspondences within the correspondences passed to the pipeline.
+    pub rejected: Vec<usize>,
+}
+
+/// Diagnostics of a single correspondence of one iteration.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CorrespondenceRecord {
+    pub iteration: usize,
+    /// Original index of the point in the fixed cloud.
+    pub fixed_idx: usize,
+    /// Original index of the point in the moved cloud.
+    pub moved_idx: usize,
+    /// Distance w.r.t. the error metric before the adjustment of this iteration.
+    pub distance: f64,
+    /// Planarity of the fixed point.
+    pub planarity: f64,
+    /// Only flags the rejection: 1 if the correspondence was used in the adjustment, 0 if rejected.
+    pub weight: f64,
+    /// Stage which rejected the correspondence, None if it was used in the adjustment.
+    pub rejected_by: Option<String>,
+}
+
+impl Correspondences<'_> {
+    // One record per correspondence, the rejected ones are taken from the reports of the stages
+    pub fn records(&self, reports: &[StageReport]) -> Vec<CorrespondenceRecord> {
+        let mut rejected_by: Vec<Option<&str>> = vec![None; self.len()];
+        for report in reports {
+            report.rejected.iter().for_each(|i| rejected_by[*i] = Some(&report.stage));
+        }
+        (0..self.len())
+            .map(|i| CorrespondenceRecord {
+                iteration: self.iteration,
+                fixed_idx: self.fixed.original_idx(i),
+                moved_idx: self.moved.original_idx(i),
+                distance: self.dist[i],
+                planarity: self.fixed.planarity(i),
+                weight: if rejected_by[i].is_some() { 0.0 } else { 1.0 },
+                rejected_by: rejected_by[i].map(str::to_string),
+            })
+            .collect()
+    }
+}
+
+// Writes the records as csv file with one line per correspondence
+pub fn write_records_to_file(records: &[CorrespondenceRecord], name: &str) {
+    let file = File::create(name).expect("Could not open file");
+    write_records(records, &mut BufWriter::new(file));
+}
+
+fn write_records(records: &[CorrespondenceRecord], writer: &mut impl Write) {
+    writeln!(writer, "iteration,fixed_idx,moved_idx,distance,planarity,weight,rejected_by")
+        .expect("Unable to write to file");
+    for r in records {
+        // Quotes within the quoted stage name are doubled (RFC 4180)
+        let rejected_by = r.rejected_by.as_deref().unwrap_or("").replace('"', "\"\"");
+        writeln!(writer, "{},{},{},{},{},{},\"{}\"", r.iteration, r.fixed_idx, r.moved_idx, r.distance,
+                 r.planarity, r.weight, rejected_by)
+            .expect("Unable to write to file");
+    }
+}
+
+/// Ordered list of rejectors, each of which sees only the correspondences kept by its predecessors.
+#[derive(Debug)]
+pub struct RejectionPipeline {
+    stages: Vec<Box<dyn CorrespondenceRejector>>,
+}
+
+impl Default for RejectionPipeline {
+    fn default() -> Self {
+        RejectionPipeline::new(vec![Box::new(MadRejector { factor: 3.0 })])
+    }
+}
+
+impl RejectionPipeline {
+    pub fn new(stages: Vec<Box<dyn CorrespondenceRejector>>) -> RejectionPipeline {
+        RejectionPipeline { stages }
+    }
+
+    // Parses a comma separated list of stages, e.g. "distance:0.5,mad:3,angle:30":
+    //   mad:FACTOR, distance:MAX, trimmed:KEEP_RATIO, planarity:MIN, angle:MAX_DEGREE,
+    //   boundary:NEIGHBORS:FACTOR, shrinking:INITIAL:FACTOR:MIN
+    pub fn parse(spec: &str) -> Result<RejectionPipeline, String> {
+        let stages = spec.split(',')
+            .map(str::trim)
+            .filter(|stage| !stage.is_empty())
+            .map(|stage| {
+                let mut parts = stage.split(':');
+                let name = parts.next().unwrap();
+                let args: Vec<&str> = parts.collect();
+                let invalid = |i: usize| format!("Invalid argument '{}' of stage '{}'", args[i], stage);
+                let number = |i: usize| args[i].parse::<f64>().map_err(|_| invalid(i));
+                let arity = |n: usize| if args.len() == n {
+                    Ok(())
+                } else {
+                    Err(format!("Stage '{}' expects {} argument(s)", name, n))
+                };
+                let rejector: Box<dyn CorrespondenceRejector> = match name {
+                    "mad" => arity(1).and_then(|_| Ok(Box::new(MadRejector { factor: number(0)? }) as _))?,
+                    "distance" => arity(1).and_then(|_| Ok(Box::new(DistanceRejector { max_distance: number(0)? }) as _))?,
+                    "trimmed" => arity(1).and_then(|_| Ok(Box::new(TrimmedRejector { keep_ratio: number(0)? }) as _))?,
+                    "planarity" => arity(1).and_then(|_| Ok(Box::new(PlanarityRejector { min_planarity: number(0)? }) as _))?,
+                    "angle" => arity(1).and_then(|_| Ok(Box::new(NormalAngleRejector { max_angle: number(0)? }) as _))?,
+                    "boundary" => arity(2).and_then(|_| {
+                        // At least two neighbors, as the centroid of a single one is always at its mean distance
+                        let neighbors = args[0].parse::<usize>().ok()
+                            .filter(|n| *n >= 2)
+                            .ok_or_else(|| format!("{}, expected an integer number of neighbors >= 2", invalid(0)))?;
+                        let factor = number(1).ok()
+                            .filter(|f| *f > 0.0)
+                            .ok_or_else(|| format!("{}, expected a factor > 0", invalid(1)))?;
+                        Ok(Box::new(BoundaryRejector { neighbors, factor }) as _)
+                    })?,
+                    "shrinking" => arity(3).and_then(|_| {
+                        Ok(Box::new(ShrinkingDistanceRejector { initial: number(0)?, factor: number(1)?, min: number(2)? }) as _)
+                    })?,
+                    _ => return Err(format!("Unknown rejection stage '{}'", name)),
+                };
+                Ok(rejector)
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(RejectionPipeline::new(stages))
+    }
+
+    pub fn needs_fixed_normals(&self) -> bool {
+        self.stages.iter().any(|stage| stage.needs_fixed_normals())
+    }
+
+    pub fn needs_moved_normals(&self) -> bool {
+        self.stages.iter().any(|stage| stage.needs_moved_normals())
+    }
+
+    // Returns the positions of the kept correspondences and a report of each stage
+    pub fn run(&self, corr: &Correspondences) -> (Vec<usize>, Vec<StageReport>) {
+        let mut kept: Vec<usize> = (0..corr.len()).collect();
+        let mut current = corr.clone();
+        let mut reports = Vec::with_capacity(self.stages.len());
+        for stage in &self.stages {
+            let keep = stage.keep(&current);
+            let mut is_kept = vec![false; kept.len()];
+            keep.iter().for_each(|i| is_kept[*i] = true);
+            reports.push(StageReport {
+                stage: stage.name().to_string(),
+                before: kept.len(),
+                after: keep.len(),
+                rejected: (0..kept.len()).filter(|i| !is_kept[*i]).map(|i| kept[i]).collect(),
+            });
+            kept = keep.iter().map(|i| kept[*i]).collect();
+            current = current.subset(&keep);
+        }
+        (kept, reports)
+    }
+}
+
+// Returns the positions of the correspondences (position i of fixed corresponds to position i of
+// moved), whose normals enclose an angle of at most max_angle (in radian). As the sign of the normals
+// is arbitrary, the smaller of the two possible angles is used. Missing normals are rejected.
+pub fn reject_by_normal_angle(fixed: &CloudView, moved: &CloudView, max_angle: f64) -> Vec<usize> {
+    assert_eq!(fixed.len(), moved.len());
+    let min_cos = max_angle.cos();
+    (0..fixed.len())
+        .filter(|i| {
+            let (n1, n2) = (fixed.normal(*i), moved.normal(*i));
+            let cos = (0..3).map(|axis| n1[axis] * n2[axis]).sum::<f64>().abs();
+            cos >= min_cos
         })
-        .collect();
+        .collect()
+}
 
-    keep
+// Returns the positions of the reciprocal correspondences (position i of fixed corresponds to
+// position i of moved), i.e. those for which the fixed point is also the nearest neighbor of the moved
+// point among all points of reference. Reference is the part of the fixed cloud in which
+// correspondences may lie (e.g. the overlap before sampling) and has to contain the fixed points.
+pub fn reciprocal_correspondences(reference: &CloudView, fixed: &CloudView, moved: &CloudView) -> Vec<usize> {
+    assert_eq!(fixed.len(), moved.len());
+    let nn = knn_search(reference, moved, 1);
+    (0..fixed.len())
+        .filter(|i| {
+            let (p1, p2) = (fixed.point(*i), moved.point(*i));
+            let dist = (0..3).map(|axis| (p1[axis] - p2[axis]).powi(2)).sum::<f64>();
+            // Ties, e.g. duplicate points, count as reciprocal
+            nn[*i][0].idx == fixed.original_idx(*i) || dist <= nn[*i][0].distance
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod corrpts_test {
-    use ndarray::{array, Array, Array1};
+    use ndarray::array;
+
+    use crate::cloud_view::CloudView;
+    use crate::corrpts::{
+        CorrespondenceRecord, Correspondences, reciprocal_correspondences, reject_by_normal_angle, RejectionPipeline,
+        write_records,
+    };
+    use crate::pointcloud::PointCloud;
+
+    #[test]
+    fn reject_wall_to_floor_matches() {
+        let floor = PointCloud::with_normals(vec![0.0; 9], vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0]);
+        let moved = PointCloud::with_normals(vec![0.0; 9], vec![0.0, 0.1, -1.0, 1.0, 0.0, 0.0, f64::NAN, 0.0, 0.0]);
+        let valid = reject_by_normal_angle(&floor.view(), &moved.view(), 10f64.to_radians());
+        assert_eq!(valid, vec![0]);
+    }
 
-    use crate::corrpts::get_median;
+    #[test]
+    fn keep_mutual_nearest_pairs() {
+        let fixed = PointCloud::new(vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 2.0, 0.0, 0.0]);
+        let moved = PointCloud::new(vec![0.1, 0.0, 0.0, 1.1, 0.0, 0.0]);
+        // The last fixed point is matched with the boundary point of the moved cloud
+        let matched = CloudView::from_indices(&moved, vec![0, 1, 1]);
+        assert_eq!(reciprocal_correspondences(&fixed.view(), &fixed.view(), &matched), vec![0, 1]);
+
+        // Only the fixed points 0 and 2 are sampled, but the nearest fixed point of the moved point
+        // 1 is still the point 1 of the whole fixed cloud
+        let sampled = CloudView::from_indices(&fixed, vec![0, 2]);
+        let matched = CloudView::from_indices(&moved, vec![0, 1]);
+        assert_eq!(reciprocal_correspondences(&fixed.view(), &sampled, &matched), vec![0]);
+        assert_eq!(reciprocal_correspondences(&sampled, &sampled, &matched), vec![0, 1]);
+    }
 
     #[test]
-    fn test_get_dists_median() {
-        let arr1: Array1<f64> = Array::linspace(0., 1.0, 9);
-        assert_eq!(get_median(&arr1), 0.5);
-        let arr2: Array1<f64> = Array::linspace(1., 8.0, 8);
-        assert_eq!(get_median(&arr2), 4.5);
-        let arr3: Array1<f64> = array![3.0, 0.0, 1.0]; // Test that values are sorted beforehand
-        assert_eq!(get_median(&arr3), 1.0);
-    }
-}
\ No newline at end of file
+    fn pipeline_applies_stages_in_order() {
+        let cloud = PointCloud::new((0..30).map(|v| v as f64).collect());
+        let corr = Correspondences {
+            fixed: cloud.view(),
+            moved: cloud.view(),
+            dist: array![0.1, -0.2, 5.0, 0.3, -0.05, 0.15, 2.0, -0.1, 0.0, 0.25],
+            signed: true,
+            iteration: 2,
+        };
+
+        let pipeline = RejectionPipeline::parse("distance:1, trimmed:0.5, shrinking:1:0.5:0.01").unwrap();
+        let (kept, reports) = pipeline.run(&corr);
+        // Shrinking threshold in iteration 2 is 1 * 0.5^2 = 0.25
+        assert_eq!(kept, vec![0, 4, 7, 8]);
+        let counts: Vec<(usize, usize)> = reports.iter().map(|r| (r.before, r.after)).collect();
+        assert_eq!(counts, vec![(10, 8), (8, 4), (4, 4)]);
+        assert_eq!(reports[0].rejected, vec![2, 6]);
+
+        let records = corr.records(&reports);
+        assert_eq!(reports.iter().map(|r| r.stage.as_str()).collect::<Vec<_>>(), vec!["distance", "trimmed", "shrinking"]);
+        assert_eq!(records[6].rejected_by.as_deref(), Some("distance"));
+        assert_eq!(records[3].rejected_by.as_deref(), Some("trimmed"));
+        assert_eq!((records[4].moved_idx, records[4].distance, records[4].weight), (4, -0.05, 1.0));
+
+        assert!(RejectionPipeline::parse("mad").is_err());
+        assert!(RejectionPipeline::parse("foo:1").is_err());
+        assert!(RejectionPipeline::parse("boundary:10:2,angle:30").unwrap().needs_moved_normals());
+        assert!(RejectionPipeline::parse("mad:3,planarity:0.3").unwrap().needs_fixed_normals());
+        assert!(!RejectionPipeline::parse("mad:3,boundary:10:2").unwrap().needs_fixed_normals());
+        for spec in ["boundary:10.5:2", "boundary:1:2", "boundary:-3:2", "boundary:10:0", "boundary:10:x"] {
+            assert!(RejectionPipeline::parse(spec).is_err(), "Accepted {}", spec);
+        }
+    }
+
+    #[test]
+    fn mad_keeps_closest_unsigned_matches() {
+        let cloud = PointCloud::new((0..30).map(|v| v as f64).collect());
+        let mut corr = Correspondences {
+            fixed: cloud.view(),
+            moved: cloud.view(),
+            dist: array![0.0, 0.2, 0.21, 0.19, 0.2, 0.22, 0.18, 0.2, 0.01, 3.0],
+            signed: false,
+            iteration: 0,
+        };
+        let pipeline = RejectionPipeline::parse("mad:3").unwrap();
+        // Point-to-point distances: only the far outlier is rejected, not the perfect matches 0 and 8
+        assert_eq!(pipeline.run(&corr).0, vec![0, 1, 2, 3, 4, 5, 6, 7, 8]);
+
+        corr.signed = true;
+        assert_eq!(pipeline.run(&corr).0, vec![1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn csv_escapes_stage_names() {
+        let record = |rejected_by: Option<&str>| CorrespondenceRecord {
+            iteration: 1,
+            fixed_idx: 7,
+            moved_idx: 3,
+            distance: -0.5,
+            planarity: 0.25,
+            weight: if rejected_by.is_some() { 0.0 } else { 1.0 },
+            rejected_by: rejected_by.map(str::to_string),
+        };
+        let mut csv = Vec::new();
+        write_records(&[record(None), record(Some("say \"hi\", twice"))], &mut csv);
+        let lines: Vec<&str> = std::str::from_utf8(&csv).unwrap().lines().collect();
+        assert_eq!(lines, vec![
+            "iteration,fixed_idx,moved_idx,distance,planarity,weight,rejected_by",
+            "1,7,3,-0.5,0.25,1,\"\"",
+            "1,7,3,-0.5,0.25,0,\"say \"\"hi\"\", twice\"",
+        ]);
+    }
+}
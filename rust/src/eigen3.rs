@@ -0,0 +1,287 @@
+// Allocation-free building blocks for the local plane fit: in-place accumulation of a 3x3
+// covariance matrix and a closed-form eigensolver for symmetric 3x3 matrices, following
+// D. Eberly, "A Robust Eigensolver for 3 x 3 Symmetric Matrices" (2014).
+
+use std::f64::consts::PI;
+
+/// Accumulates (weighted) first and second moments of points, shifted by `origin` to avoid
+/// catastrophic cancellation for large coordinates.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Covariance3 {
+    origin: [f64; 3],
+    sum_weights: f64,
+    sum: [f64; 3],
+    // xx, xy, xz, yy, yz, zz
+    sum_sq: [f64; 6],
+}
+
+impl Covariance3 {
+    pub fn new(origin: [f64; 3]) -> Covariance3 {
+        Covariance3 { origin, ..Default::default() }
+    }
+
+    pub fn add(&mut self, p: &[f64; 3]) {
+        self.add_weighted(p, 1.0);
+    }
+
+    pub fn add_weighted(&mut self, p: &[f64; 3], weight: f64) {
+        let [x, y, z] = [p[0] - self.origin[0], p[1] - self.origin[1], p[2] - self.origin[2]];
+        self.sum_weights += weight;
+        self.sum[0] += weight * x;
+        self.sum[1] += weight * y;
+        self.sum[2] += weight * z;
+        self.sum_sq[0] += weight * x * x;
+        self.sum_sq[1] += weight * x * y;
+        self.sum_sq[2] += weight * x * z;
+        self.sum_sq[3] += weight * y * y;
+        self.sum_sq[4] += weight * y * z;
+        self.sum_sq[5] += weight * z * z;
+    }
+
+    pub fn mean(&self) -> [f64; 3] {
+        let w = self.sum_weights;
+        [
+            self.origin[0] + self.sum[0] / w,
+            self.origin[1] + self.sum[1] / w,
+            self.origin[2] + self.sum[2] / w,
+        ]
+    }
+
+    /// Covariance matrix, normalized by `sum of weights - ddof` (same meaning as in ndarray-stats).
+    pub fn covariance(&self, ddof: f64) -> [[f64; 3]; 3] {
+        let w = self.sum_weights;
+        let m = [self.sum[0] / w, self.sum[1] / w, self.sum[2] / w];
+        let d = w - ddof;
+        let c = |sq: f64, i: usize, j: usize| (sq - w * m[i] * m[j]) / d;
+
+        let xx = c(self.sum_sq[0], 0, 0);
+        let xy = c(self.sum_sq[1], 0, 1);
+        let xz = c(self.sum_sq[2], 0, 2);
+        let yy = c(self.sum_sq[3], 1, 1);
+        let yz = c(self.sum_sq[4], 1, 2);
+        let zz = c(self.sum_sq[5], 2, 2);
+        [[xx, xy, xz], [xy, yy, yz], [xz, yz, zz]]
+    }
+}
+
+/// Eigen decomposition of a symmetric 3x3 matrix. Eigenvalues are sorted in descending order,
+/// `vectors[i]` is the unit eigenvector of `values[i]`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SymmetricEigen3 {
+    pub values: [f64; 3],
+    pub vectors: [[f64; 3]; 3],
+}
+
+impl SymmetricEigen3 {
+    pub fn new(a: &[[f64; 3]; 3]) -> SymmetricEigen3 {
+        // Scale the matrix to avoid floating point overflow
+        let max_abs = a.iter().flatten().fold(0.0_f64, |max, v| max.max(v.abs()));
+        if max_abs == 0.0 {
+            return SymmetricEigen3 {
+                values: [0.0; 3],
+                vectors: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            };
+        }
+        let scaled = a.map(|row| row.map(|v| v / max_abs));
+
+        let [l1, l2, l3] = eigenvalues_scaled(&scaled);
+
+        // Compute the eigenvector of the better separated eigenvalue first
+        let vectors = if l1 - l2 >= l2 - l3 {
+            let v1 = eigenvector_of_simple(&scaled, l1);
+            let v2 = eigenvector_in_complement(&scaled, &v1, l2);
+            [v1, v2, cross(&v1, &v2)]
+        } else {
+            let v3 = eigenvector_of_simple(&scaled, l3);
+            let v2 = eigenvector_in_complement(&scaled, &v3, l2);
+            [cross(&v2, &v3), v2, v3]
+        };
+
+        SymmetricEigen3 { values: [l1 * max_abs, l2 * max_abs, l3 * max_abs], vectors }
+    }
+}
+
+/// Eigenvalues of a symmetric 3x3 matrix in descending order.
+pub fn symmetric_eigenvalues3(a: &[[f64; 3]; 3]) -> [f64; 3] {
+    let max_abs = a.iter().flatten().fold(0.0_f64, |max, v| max.max(v.abs()));
+    if max_abs == 0.0 {
+        return [0.0; 3];
+    }
+    eigenvalues_scaled(&a.map(|row| row.map(|v| v / max_abs))).map(|v| v * max_abs)
+}
+
+/// Covariance of a plane with unit variance within the plane and variance `epsilon` along its
+/// normal, i.e. `I - (1 - epsilon) * n * n^T` (regularization of Generalized-ICP, Segal et al. 2009).
+pub fn plane_covariance(normal: &[f64; 3], epsilon: f64) -> [[f64; 3]; 3] {
+    [0, 1, 2].map(|i| [0, 1, 2].map(|j| {
+        let identity = if i == j { 1.0 } else { 0.0 };
+        identity - (1.0 - epsilon) * normal[i] * normal[j]
+    }))
+}
+
+// Trigonometric solution of the characteristic polynomial
+fn eigenvalues_scaled(a: &[[f64; 3]; 3]) -> [f64; 3] {
+    let off_diagonal = a[0][1] * a[0][1] + a[0][2] * a[0][2] + a[1][2] * a[1][2];
+    if off_diagonal == 0.0 {
+        let mut values = [a[0][0], a[1][1], a[2][2]];
+        values.sort_by(|x, y| y.total_cmp(x));
+        return values;
+    }
+
+    let q = (a[0][0] + a[1][1] + a[2][2]) / 3.0;
+    let b00 = a[0][0] - q;
+    let b11 = a[1][1] - q;
+    let b22 = a[2][2] - q;
+    let p = ((b00 * b00 + b11 * b11 + b22 * b22 + 2.0 * off_diagonal) / 6.0).sqrt();
+
+    let det = b00 * (b11 * b22 - a[1][2] * a[1][2])
+        - a[0][1] * (a[0][1] * b22 - a[1][2] * a[0][2])
+        + a[0][2] * (a[0][1] * a[1][2] - b11 * a[0][2]);
+    let half_det = (det / (2.0 * p * p * p)).clamp(-1.0, 1.0);
+    let angle = half_det.acos() / 3.0;
+
+    let l1 = q + 2.0 * p * angle.cos();
+    let l3 = q + 2.0 * p * (angle + 2.0 * PI / 3.0).cos();
+    let l2 = 3.0 * q - l1 - l3;
+    [l1, l2, l3]
+}
+
+// Eigenvector of an eigenvalue with multiplicity 1: the largest cross product of two rows of A - lI
+fn eigenvector_of_simple(a: &[[f64; 3]; 3], value: f64) -> [f64; 3] {
+    let r0 = [a[0][0] - value, a[0][1], a[0][2]];
+    let r1 = [a[0][1], a[1][1] - value, a[1][2]];
+    let r2 = [a[0][2], a[1][2], a[2][2] - value];
+
+    let candidates = [cross(&r0, &r1), cross(&r0, &r2), cross(&r1, &r2)];
+    let best = candidates.iter()
+        .max_by(|x, y| dot(x, x).total_cmp(&dot(y, y)))
+        .unwrap();
+    let norm = dot(best, best).sqrt();
+    if norm == 0.0 {
+        return [1.0, 0.0, 0.0];
+    }
+    best.map(|v| v / norm)
+}
+
+// Eigenvector of `value` within the plane orthogonal to the eigenvector `w`
+fn eigenvector_in_complement(a: &[[f64; 3]; 3], w: &[f64; 3], value: f64) -> [f64; 3] {
+    let (u, v) = orthogonal_complement(w);
+    let au = mat_vec(a, &u);
+    let av = mat_vec(a, &v);
+
+    let mut m00 = dot(&u, &au) - value;
+    let mut m01 = dot(&u, &av);
+    let mut m11 = dot(&v, &av) - value;
+    let (abs00, abs01, abs11) = (m00.abs(), m01.abs(), m11.abs());
+
+    let combine = |s: f64, t: f64| [s * u[0] - t * v[0], s * u[1] - t * v[1], s * u[2] - t * v[2]];
+    if abs00 >= abs11 {
+        if abs00.max(abs01) == 0.0 {
+            return u;
+        }
+        if abs00 >= abs01 {
+            m01 /= m00;
+            m00 = 1.0 / (1.0 + m01 * m01).sqrt();
+            m01 *= m00;
+        } else {
+            m00 /= m01;
+            m01 = 1.0 / (1.0 + m00 * m00).sqrt();
+            m00 *= m01;
+        }
+        combine(m01, m00)
+    } else {
+        if abs11.max(abs01) == 0.0 {
+            return u;
+        }
+        if abs11 >= abs01 {
+            m01 /= m11;
+            m11 = 1.0 / (1.0 + m01 * m01).sqrt();
+            m01 *= m11;
+        } else {
+            m11 /= m01;
+            m01 = 1.0 / (1.0 + m11 * m11).sqrt();
+            m11 *= m01;
+        }
+        combine(m11, m01)
+    }
+}
+
+fn orthogonal_complement(w: &[f64; 3]) -> ([f64; 3], [f64; 3]) {
+    let u = if w[0].abs() > w[1].abs() {
+        let inv = 1.0 / (w[0] * w[0] + w[2] * w[2]).sqrt();
+        [-w[2] * inv, 0.0, w[0] * inv]
+    } else {
+        let inv = 1.0 / (w[1] * w[1] + w[2] * w[2]).sqrt();
+        [0.0, w[2] * inv, -w[1] * inv]
+    };
+    (u, cross(w, &u))
+}
+
+fn mat_vec(a: &[[f64; 3]; 3], v: &[f64; 3]) -> [f64; 3] {
+    [dot(&a[0], v), dot(&a[1], v), dot(&a[2], v)]
+}
+
+fn dot(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+
+#[cfg(test)]
+mod eigen3_test {
+    use linfa_linalg::eigh::{EighInto, EigSort};
+    use ndarray::Array2;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::eigen3::{Covariance3, SymmetricEigen3};
+
+    #[test]
+    fn matches_general_eigensolver() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        for _ in 0..1000 {
+            // Covariance of a random, anisotropic point set
+            let scale: [f64; 3] = [rng.gen_range(0.01..10.0), rng.gen_range(0.01..10.0), rng.gen_range(1e-6..0.1)];
+            let offset = rng.gen_range(-1e5..1e5);
+            let points: Vec<[f64; 3]> = (0..10)
+                .map(|_| [0, 1, 2].map(|axis| offset + scale[axis] * rng.gen_range(-1.0..1.0)))
+                .collect();
+
+            let mut cov = Covariance3::new(points[0]);
+            points.iter().for_each(|p| cov.add(p));
+            let c = cov.covariance(1.0);
+            let eigen = SymmetricEigen3::new(&c);
+
+            let matrix = Array2::from_shape_fn((3, 3), |(i, j)| c[i][j]);
+            let (values, vectors) = matrix.eigh_into().unwrap().sort_eig_desc();
+            for i in 0..3 {
+                assert_float_absolute_eq!(eigen.values[i], values[i], 1e-9 * values[0]);
+            }
+            // Compare normals up to their sign
+            let dot: f64 = (0..3).map(|axis| eigen.vectors[2][axis] * vectors[[axis, 2]]).sum();
+            assert_float_absolute_eq!(dot.abs(), 1.0, 1e-6);
+        }
+    }
+
+    #[test]
+    fn handles_repeated_eigenvalues() {
+        let eigen = SymmetricEigen3::new(&[[2.0, 0.0, 0.0], [0.0, 2.0, 0.0], [0.0, 0.0, 1.0]]);
+        assert_eq!(eigen.values, [2.0, 2.0, 1.0]);
+        assert_float_absolute_eq!(eigen.vectors[2][2].abs(), 1.0, 1e-12);
+
+        let eigen = SymmetricEigen3::new(&[[1.0, 1.0, 1.0], [1.0, 1.0, 1.0], [1.0, 1.0, 1.0]]);
+        assert_float_absolute_eq!(eigen.values[0], 3.0, 1e-12);
+        let v = eigen.vectors;
+        for (i, j) in [(0, 1), (0, 2), (1, 2)] {
+            let dot: f64 = (0..3).map(|axis| v[i][axis] * v[j][axis]).sum();
+            assert_float_absolute_eq!(dot, 0.0, 1e-12);
+        }
+    }
+}
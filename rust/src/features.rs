@@ -0,0 +1,100 @@
+use ndarray::{Array2, ArrayView1};
+
+use crate::pointcloud::Neighborhood;
+
+/// Eigenvalue-based descriptors of the local geometry, see Weinmann et al. (2015).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GeometricFeature {
+    Linearity,
+    Planarity,
+    Sphericity,
+    Omnivariance,
+    Anisotropy,
+    Eigenentropy,
+    ChangeOfCurvature,
+}
+
+impl GeometricFeature {
+    pub const ALL: [GeometricFeature; 7] = [
+        GeometricFeature::Linearity,
+        GeometricFeature::Planarity,
+        GeometricFeature::Sphericity,
+        GeometricFeature::Omnivariance,
+        GeometricFeature::Anisotropy,
+        GeometricFeature::Eigenentropy,
+        GeometricFeature::ChangeOfCurvature,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GeometricFeature::Linearity => "linearity",
+            GeometricFeature::Planarity => "planarity",
+            GeometricFeature::Sphericity => "sphericity",
+            GeometricFeature::Omnivariance => "omnivariance",
+            GeometricFeature::Anisotropy => "anisotropy",
+            GeometricFeature::Eigenentropy => "eigenentropy",
+            GeometricFeature::ChangeOfCurvature => "change_of_curvature",
+        }
+    }
+
+    // Column of the feature within Features::values
+    fn column(&self) -> usize {
+        GeometricFeature::ALL.iter().position(|f| f == self).unwrap()
+    }
+
+    /// Computes the feature from the eigenvalues of the local covariance matrix (sorted descending).
+    pub fn from_eigenvalues(&self, eigenvalues: &[f64; 3]) -> f64 {
+        // Tiny negative eigenvalues may arise from numerical noise
+        let [l1, l2, l3] = eigenvalues.map(|ev| ev.max(0.0));
+        let sum = l1 + l2 + l3;
+        let [e1, e2, e3] = [l1 / sum, l2 / sum, l3 / sum];
+
+        match self {
+            GeometricFeature::Linearity => (l1 - l2) / l1,
+            GeometricFeature::Planarity => (l2 - l3) / l1,
+            GeometricFeature::Sphericity => l3 / l1,
+            GeometricFeature::Omnivariance => (e1 * e2 * e3).cbrt(),
+            GeometricFeature::Anisotropy => (l1 - l3) / l1,
+            GeometricFeature::Eigenentropy => {
+                -[e1, e2, e3].iter().filter(|e| **e > 0.0).map(|e| e * e.ln()).sum::<f64>()
+            }
+            GeometricFeature::ChangeOfCurvature => l3 / sum,
+        }
+    }
+}
+
+/// All geometric features of a point cloud, estimated at a single scale.
+pub struct Features {
+    pub neighborhood: Neighborhood,
+    // One row per point and one column per GeometricFeature; NaN for points without estimate
+    pub values: Array2<f64>,
+}
+
+impl Features {
+    pub fn get(&self, feature: GeometricFeature) -> ArrayView1<'_, f64> {
+        self.values.column(feature.column())
+    }
+}
+
+
+#[cfg(test)]
+mod features_test {
+    use crate::features::GeometricFeature;
+
+    #[test]
+    fn features_from_eigenvalues() {
+        let linear = [1.0, 0.0, 0.0];
+        assert_eq!(GeometricFeature::Linearity.from_eigenvalues(&linear), 1.0);
+        assert_eq!(GeometricFeature::Eigenentropy.from_eigenvalues(&linear), 0.0);
+
+        let planar = [1.0, 1.0, 0.0];
+        assert_eq!(GeometricFeature::Planarity.from_eigenvalues(&planar), 1.0);
+        assert_eq!(GeometricFeature::ChangeOfCurvature.from_eigenvalues(&planar), 0.0);
+
+        let isotropic = [1.0, 1.0, 1.0];
+        assert_eq!(GeometricFeature::Sphericity.from_eigenvalues(&isotropic), 1.0);
+        assert_eq!(GeometricFeature::Anisotropy.from_eigenvalues(&isotropic), 0.0);
+        assert_float_absolute_eq!(GeometricFeature::Omnivariance.from_eigenvalues(&isotropic), 1.0 / 3.0, 1e-12);
+        assert_float_absolute_eq!(GeometricFeature::Eigenentropy.from_eigenvalues(&isotropic), 3.0_f64.ln(), 1e-12);
+    }
+}
@@ -0,0 +1,415 @@
+use std::path::Path;
+use std::time::Instant;
+
+use ndarray::{Array, Array1, Array2, s};
+
+use crate::cloud_view::CloudView;
+use crate::corrpts::{
+    CorrespondenceRecord, Correspondences, reciprocal_correspondences, RejectionPipeline, StageReport, write_records_to_file,
+};
+use crate::pointcloud::{NormalStatus, PointCloud};
+use crate::rigid_body_transformation::{ErrorMetric, rotation_matrix_to_euler_angles};
+use crate::sampling::SamplingStrategy;
+
+#[derive(Debug)]
+pub struct Parameters {
+    pub max_overlap_distance: f64,
+    pub correspondences: usize,
+    pub neighbors: usize,
+    pub max_iterations: usize,
+    pub error_metric: ErrorMetric,
+    // Only mutual nearest neighbors are used as correspondences
+    pub reciprocal: bool,
+    // Stages which reject false correspondences, applied in order
+    pub rejection: RejectionPipeline,
+    // Iteration stops if mean and std of the residuals change less than min_change (in %)
+    pub min_change: f64,
+    pub sampling: SamplingStrategy,
+    // Points of both clouds must be within max_overlap_distance of the other cloud
+    pub mutual_overlap: bool,
+    // The overlap is estimated again every overlap_interval iterations (never if 0)
+    pub overlap_interval: usize,
+    // Directory for debug files, e.g. the selected points; nothing is written if None
+    pub debug_dir: Option<String>,
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Parameters {
+            max_overlap_distance: 1.0,
+            correspondences: 1000,
+            neighbors: 10,
+            max_iterations: 100,
+            error_metric: ErrorMetric::PointToPlane,
+            reciprocal: false,
+            rejection: RejectionPipeline::default(),
+            min_change: 1.0,
+            sampling: SamplingStrategy::Stride,
+            mutual_overlap: false,
+            overlap_interval: 0,
+            debug_dir: None,
+        }
+    }
+}
+
+pub struct RegistrationResult {
+    /// Homogeneous transformation matrix which maps the moved onto the fixed point cloud.
+    pub h: Array2<f64>,
+    /// alpha1, alpha2, alpha3 (rotation angles in radian), tx, ty, tz of h.
+    pub parameters: [f64; 6],
+    /// A posteriori standard deviations of the parameters of the last adjustment.
+    pub uncertainties: [f64; 6],
+    /// Condition number of the normal matrix of the last adjustment. Large values indicate that
+    /// some parameters are poorly constrained by the correspondences.
+    pub condition_number: f64,
+    pub iterations: usize,
+    pub converged: bool,
+    /// Strategy (incl. its seed) which was used to select the correspondences.
+    pub sampling: SamplingStrategy,
+    /// One record per correspondence and iteration, incl. the rejected ones.
+    pub diagnostics: Vec<CorrespondenceRecord>,
+}
+
+impl RegistrationResult {
+    pub fn seed(&self) -> Option<u64> {
+        self.sampling.seed()
+    }
+}
+
+pub fn register(fixed: &mut PointCloud, moved: &mut PointCloud, params: &Parameters) -> RegistrationResult {
+    let initial = (fixed.selection_idx().to_vec(), moved.selection_idx().to_vec());
+    let mut overlap = select_correspondences(fixed, moved, &initial, params);
+
+    let mut h: Array2<f64> = Array::eye(4);
+    let mut uncertainties = [f64::NAN; 6];
+    let mut condition_number = f64::NAN;
+    let mut residual_mean: Vec<f64> = Vec::new();
+    let mut residual_std: Vec<f64> = Vec::new();
+    let mut iterations = 0;
+    let mut converged = false;
+    let mut diagnostics = Vec::new();
+
+    println!("Start iterations ...\n");
+    for i in 0..params.max_iterations {
+        let now = Instant::now();
+        if i > 0 && params.overlap_interval > 0 && i % params.overlap_interval == 0 {
+            println!("Estimate overlap again ...");
+            overlap = select_correspondences(fixed, moved, &initial, params);
+        }
+        let fixed_selection = fixed.selection();
+        let dist_res = PointCloud::cloud_to_cloud_distance(&fixed_selection, &moved.selection());
+
+        let matched_idx: Vec<usize> = dist_res.nn.iter().map(|nn| nn[0].idx).collect();
+        if params.rejection.needs_moved_normals() || params.error_metric.needs_moved_normals() {
+            moved.estimate_missing_normals(&matched_idx, params.neighbors);
+        }
+        let moved_matched = CloudView::from_indices(moved, matched_idx);
+        let dist = (0..fixed_selection.len())
+            .map(|j| params.error_metric.distance(fixed_selection.point(j), fixed_selection.normal(j), moved_matched.point(j)))
+            .collect();
+        let correspondences = Correspondences {
+            fixed: fixed_selection,
+            moved: moved_matched,
+            dist,
+            signed: params.error_metric.signed_distance(),
+            iteration: i,
+        };
+        // Positions of the rejected correspondences within all correspondences of this iteration
+        // are kept in the reports for the diagnostics
+        let mut reports = Vec::new();
+        let mut candidates: Vec<usize> = (0..correspondences.len()).collect();
+        if params.error_metric.needs_moved_normals() {
+            let valid = |j: usize| correspondences.moved.normal_status(j) == NormalStatus::Valid;
+            reports.push(StageReport {
+                stage: "moved normal".to_string(),
+                before: candidates.len(),
+                after: candidates.iter().filter(|j| valid(**j)).count(),
+                rejected: candidates.iter().copied().filter(|j| !valid(*j)).collect(),
+            });
+            candidates.retain(|j| valid(*j));
+        }
+        if params.reciprocal {
+            // The moved points are searched back in the whole overlap, not only in the sampled points
+            let reference = CloudView::from_indices(fixed, &overlap[..]);
+            let mutual = reciprocal_correspondences(&reference, &correspondences.fixed, &correspondences.moved);
+            let mut is_mutual = vec![false; correspondences.len()];
+            mutual.iter().for_each(|i| is_mutual[*i] = true);
+            reports.push(StageReport {
+                stage: "reciprocal".to_string(),
+                before: candidates.len(),
+                after: candidates.iter().filter(|j| is_mutual[**j]).count(),
+                rejected: candidates.iter().copied().filter(|j| !is_mutual[*j]).collect(),
+            });
+            candidates.retain(|j| is_mutual[*j]);
+        }
+        let (valid_idx, stage_reports) = params.rejection.run(&correspondences.subset(&candidates));
+        let valid_idx: Vec<usize> = valid_idx.iter().map(|i| candidates[*i]).collect();
+        reports.extend(stage_reports.into_iter().map(|report| StageReport {
+            rejected: report.rejected.iter().map(|i| candidates[*i]).collect(),
+            ..report
+        }));
+        for report in &reports {
+            println!("{:>9}   {}: {} -> {} ({:.1}% kept)", "", report.stage, report.before, report.after,
+                     report.after as f64 / report.before as f64 * 100.0);
+        }
+        let valid = correspondences.subset(&valid_idx);
+        let (fixed_valid, moved_valid) = (&valid.fixed, &valid.moved);
+        if fixed_valid.len() < 6 {
+            panic!("Too few correspondences ({}) left after rejection.", fixed_valid.len());
+        }
+
+        let transformation = params.error_metric.estimate(fixed_valid, moved_valid);
+        let initial_residuals = (mean(&valid.dist), std(&valid.dist));
+        diagnostics.extend(correspondences.records(&reports));
+        moved.transform(&transformation.h);
+        h = transformation.h.dot(&h);
+        uncertainties = transformation.uncertainties;
+        condition_number = transformation.condition_number;
+        iterations = i + 1;
+
+        residual_mean.push(mean(&transformation.residuals));
+        residual_std.push(std(&transformation.residuals));
+
+        if i == 0 {
+            println!("{:>9} | {:>15} | {:>15} | {:>15} | {:>9}",
+                     "Iteration", "correspondences", "mean(residuals)", "std(residuals)", "time[ms]");
+            println!("{:>9} | {:>15} | {:>15.4} | {:>15.4} |",
+                     "orig:0", valid_idx.len(), initial_residuals.0, initial_residuals.1);
+        }
+        println!("{:>9} | {:>15} | {:>15.4} | {:>15.4} | {:>9}",
+                 iterations, valid_idx.len(), residual_mean[i], residual_std[i], now.elapsed().as_millis());
+
+        if i > 0
+            && change(residual_mean[i], residual_mean[i - 1]) < params.min_change
+            && change(residual_std[i], residual_std[i - 1]) < params.min_change {
+            println!("Convergence criteria fulfilled -> stop iteration!");
+            converged = true;
+            break;
+        }
+    }
+
+    println!("Estimated transformation matrix H:");
+    for row in h.outer_iter() {
+        println!("[{:12.6} {:12.6} {:12.6} {:12.6}]", row[0], row[1], row[2], row[3]);
+    }
+    println!("Condition number of the normal matrix: {:.1}", condition_number);
+    if let Some(dir) = &params.debug_dir {
+        write_records_to_file(&diagnostics, Path::new(dir).join("correspondences.csv").to_str().unwrap());
+    }
+
+    let angles = rotation_matrix_to_euler_angles(&h.slice(s![..3, ..3]).to_owned());
+    RegistrationResult {
+        parameters: [angles[0], angles[1], angles[2], h[[0, 3]], h[[1, 3]], h[[2, 3]]],
+        h,
+        uncertainties,
+        condition_number,
+        iterations,
+        converged,
+        sampling: params.sampling.clone(),
+        diagnostics,
+    }
+}
+
+fn mean(values: &Array1<f64>) -> f64 {
+    values.mean().unwrap_or(f64::NAN)
+}
+
+fn std(values: &Array1<f64>) -> f64 {
+    values.std(1.0)
+}
+
+// Selects the points of the fixed cloud for which correspondences are searched, i.e. restricts both
+// clouds (starting from their selections before the registration) to their overlap w.r.t. the current
+// position of the moved cloud and samples the fixed one. Returns the original indices of the fixed
+// points within the overlap (before sampling).
+fn select_correspondences(fixed: &mut PointCloud, moved: &mut PointCloud, initial: &(Vec<usize>, Vec<usize>), params: &Parameters) -> Vec<usize> {
+    let debug_file = |name: &str| params.debug_dir.as_ref().map(|dir| Path::new(dir).join(name));
+    fixed.set_selection(&initial.0);
+    moved.set_selection(&initial.1);
+
+    if params.max_overlap_distance > 0.0 {
+        println!("Consider partial overlap of point clouds ...");
+        if params.mutual_overlap {
+            fixed.select_in_mutual_range(moved, params.max_overlap_distance);
+        } else {
+            fixed.select_in_range(moved, params.max_overlap_distance);
+        }
+        if fixed.selection_idx().is_empty() || moved.selection_idx().is_empty() {
+            panic!(
+                "Point clouds do not overlap within max_overlap_distance = {}. \
+            Consider increasing the value of max_overlap_distance.",
+                params.max_overlap_distance
+            );
+        }
+        if let Some(path) = debug_file("initial_selection.xyz") {
+            PointCloud::write_to_file(&fixed.selection(), path.to_str().unwrap());
+        }
+    }
+
+    if let SamplingStrategy::NormalSpace { .. } | SamplingStrategy::Stability = params.sampling {
+        println!("Estimate normals of overlapping points for {:?} sampling ...", params.sampling);
+        fixed.estimate_normals(params.neighbors);
+    }
+
+    let overlap = fixed.selection_idx().to_vec();
+    println!("Select points for correspondences in fixed point cloud ...");
+    fixed.select_n_pts_with(params.correspondences, &params.sampling);
+    if let Some(path) = debug_file(&format!("select_{}_pts.xyz", params.correspondences)) {
+        PointCloud::write_to_file(&fixed.selection(), path.to_str().unwrap());
+    }
+
+    if params.error_metric.needs_normals() || params.rejection.needs_fixed_normals() {
+        println!("Estimate normals of selected points ...\n");
+        fixed.estimate_normals(params.neighbors);
+    }
+    if params.error_metric.needs_colors() {
+        if !fixed.has_colors() || !moved.has_colors() {
+            panic!("{:?} needs colors of both point clouds.", params.error_metric);
+        }
+        println!("Estimate color gradients of selected points ...\n");
+        fixed.estimate_color_gradients(params.neighbors);
+    }
+    overlap
+}
+
+// Relative change in %
+fn change(new: f64, old: f64) -> f64 {
+    ((new - old) / old * 100.0).abs()
+}
+
+
+#[cfg(test)]
+pub(crate) mod icp_test {
+    use ndarray::{array, Array1, Array2, s};
+
+    use crate::corrpts::RejectionPipeline;
+    use crate::icp::{register, Parameters};
+    use crate::pointcloud::PointCloud;
+    use crate::rigid_body_transformation::{ErrorMetric, euler_angles_to_rotation_matrix, homogeneous_transformation_matrix};
+    use crate::sampling::SamplingStrategy;
+
+    // Wavy surface, which constrains all six parameters (also used by the NDT tests)
+    pub(crate) fn surface() -> PointCloud {
+        let mut points = Vec::new();
+        for i in 0..60 {
+            for j in 0..60 {
+                let (x, y) = (i as f64 * 0.1, j as f64 * 0.1);
+                points.extend_from_slice(&[x, y, 0.5 * (1.3 * x).sin() * (0.9 * y).cos() + 0.1 * x]);
+            }
+        }
+        PointCloud::new(points)
+    }
+
+    #[test]
+    fn colored_icp_fixes_in_plane_shift() {
+        // Textured plane, whose in-plane position is only constrained by the colors
+        let intensity = |x: f64, y: f64| 0.5 + 0.25 * (3.0 * x).sin() * (2.0 * y).cos();
+        let textured_plane = |dx: f64, dy: f64| {
+            let (mut points, mut colors) = (Vec::new(), Vec::new());
+            for i in 0..30 {
+                for j in 0..30 {
+                    let (x, y) = (i as f64 * 0.1 + dx, j as f64 * 0.1 + dy);
+                    points.extend_from_slice(&[x, y, 0.0]);
+                    colors.extend_from_slice(&[intensity(x, y); 3]);
+                }
+            }
+            PointCloud::with_colors(points, colors)
+        };
+        let mut fixed = textured_plane(0.0, 0.0);
+        let mut moved = textured_plane(0.0, 0.0);
+        moved.transform(&homogeneous_transformation_matrix(&Array2::eye(3), &array![0.03, -0.02, 0.01]));
+
+        let params = Parameters {
+            error_metric: ErrorMetric::Colored { color_weight: 0.5 },
+            rejection: RejectionPipeline::parse("distance:0.5").unwrap(),
+            min_change: 0.1,
+            ..Parameters::default()
+        };
+        let res = register(&mut fixed, &mut moved, &params);
+        assert!((res.parameters[3] + 0.03).abs() < 2e-3);
+        assert!((res.parameters[4] - 0.02).abs() < 2e-3);
+        assert!((res.parameters[5] + 0.01).abs() < 1e-6);
+    }
+
+    #[test]
+    fn register_recovers_known_transformation() {
+        // Rotation angles and translation of the transformation to recover
+        let offset = ([0.01, -0.02, 0.03], [0.05, -0.03, 0.02]);
+        // Point-to-point only converges to the exact counterparts if the points are displaced by well
+        // below half the point spacing (0.1), otherwise it gets stuck in a local minimum
+        let small_offset = ([0.001, -0.002, 0.003], [0.02, -0.01, 0.02]);
+
+        let variants = [
+            (Parameters { sampling: SamplingStrategy::Random { seed: 1 }, ..Parameters::default() }, offset),
+            (
+                Parameters {
+                    sampling: SamplingStrategy::Stratified { seed: 1 },
+                    rejection: RejectionPipeline::parse("mad:3,angle:20").unwrap(),
+                    ..Parameters::default()
+                },
+                offset,
+            ),
+            (
+                Parameters {
+                    sampling: SamplingStrategy::Random { seed: 1 },
+                    max_overlap_distance: 0.2,
+                    mutual_overlap: true,
+                    overlap_interval: 2,
+                    reciprocal: true,
+                    ..Parameters::default()
+                },
+                offset,
+            ),
+            (
+                Parameters {
+                    sampling: SamplingStrategy::Random { seed: 1 },
+                    error_metric: ErrorMetric::PointToPoint,
+                    rejection: RejectionPipeline::parse("mad:3,planarity:0.1,angle:20").unwrap(),
+                    ..Parameters::default()
+                },
+                small_offset,
+            ),
+            (
+                Parameters {
+                    sampling: SamplingStrategy::Random { seed: 1 },
+                    error_metric: ErrorMetric::Symmetric,
+                    ..Parameters::default()
+                },
+                offset,
+            ),
+            (
+                Parameters {
+                    sampling: SamplingStrategy::Random { seed: 1 },
+                    error_metric: ErrorMetric::Gicp,
+                    max_iterations: 20,
+                    ..Parameters::default()
+                },
+                offset,
+            ),
+        ];
+        for (params, (angles, t)) in variants {
+            let r = euler_angles_to_rotation_matrix(angles[0], angles[1], angles[2]);
+            let t = Array1::from_vec(t.to_vec());
+            let h_true = homogeneous_transformation_matrix(&r, &t);
+            // The moved cloud is the fixed cloud transformed by the inverse of h_true
+            let t_inv: Array1<f64> = -r.t().dot(&t);
+            let h_inv = homogeneous_transformation_matrix(&r.t().to_owned(), &t_inv);
+
+            let mut fixed = surface();
+            let mut moved = surface();
+            moved.transform(&h_inv);
+
+            let params = Parameters { min_change: 0.1, ..params };
+            let res = register(&mut fixed, &mut moved, &params);
+            let last = res.diagnostics.iter().filter(|r| r.iteration + 1 == res.iterations);
+            assert!(last.clone().count() > 0);
+            assert!(last.filter(|r| r.rejected_by.is_none()).all(|r| r.weight == 1.0 && r.distance.abs() < 0.01));
+            let stages = ["moved normal", "reciprocal", "mad", "planarity", "angle"];
+            assert!(res.diagnostics.iter().filter_map(|r| r.rejected_by.as_deref()).all(|stage| stages.contains(&stage)));
+            assert_eq!(res.sampling, params.sampling);
+            assert_eq!(res.seed(), Some(1));
+            let should_be_zero: Array2<f64> = &res.h - &h_true;
+            assert!(should_be_zero.slice(s![..3, ..]).iter().all(|v| v.abs() < 1e-3));
+        }
+    }
+}
@@ -0,0 +1,19 @@
+#[cfg(test)]
+#[macro_use]
+extern crate assert_float_eq;
+
+pub mod pointcloud;
+pub mod cloud_view;
+pub mod corrpts;
+pub mod eigen3;
+pub mod features;
+pub mod icp;
+pub mod ndt;
+pub mod nearest_neighbor;
+pub mod normal_estimator;
+pub mod octree;
+pub mod region;
+pub mod rigid_body_transformation;
+pub mod sampling;
+pub mod stats;
+pub mod voxel_grid;
@@ -1,98 +1,134 @@
-#[macro_use]
-extern crate assert_float_eq;
-
-use std::time::Instant;
-
-use crate::corrpts::reject;
-use crate::pointcloud::PointCloud;
-use crate::rigid_body_transformation::estimate_rigid_body_transformation;
-
-mod pointcloud;
-mod corrpts;
-mod permutation;
-mod nearest_neighbor;
-mod rigid_body_transformation;
-
-struct Parameters {
-    max_overlap_distance: f64,
-    correspondences: usize,
-    neighbors: usize,
-    max_iterations: usize,
-    min_planarity: usize,
+use std::process;
+
+use simpleicp::corrpts::RejectionPipeline;
+use simpleicp::icp::{register, Parameters};
+use simpleicp::ndt::{NdtParameters, register_ndt};
+use simpleicp::pointcloud::PointCloud;
+use simpleicp::region::read_regions;
+use simpleicp::rigid_body_transformation::ErrorMetric;
+
+const USAGE: &str = "\
+Usage: simpleicp [FIXED MOVED] [--metric METRIC] [--color-weight W] [--color-max MAX] [--reciprocal]
+                 [--reject SPEC] [--ndt RESOLUTION] [--crop FILE]
+
+  FIXED, MOVED     xyz files of the point clouds (default: bunny1.xyz bunny2.xyz), x y z r g b
+                     per line for the colored metric
+  --metric METRIC  point-to-plane (default), point-to-point, symmetric, gicp or colored
+  --color-weight W weight of the photometric term of the colored metric in [0, 1] (default: 0.03)
+  --color-max MAX  maximum value of the color channels in the files, e.g. 1 for normalized colors
+                     (default: 255)
+  --reciprocal     only use mutual nearest neighbors as correspondences
+  --reject SPEC    comma separated correspondence rejection stages, applied in order (default: mad:3)
+                     mad:FACTOR, distance:MAX, trimmed:KEEP_RATIO, planarity:MIN, angle:MAX_DEGREE,
+                     boundary:NEIGHBORS:FACTOR, shrinking:INITIAL:FACTOR:MIN
+  --ndt RESOLUTION register with the Normal Distributions Transform of the fixed cloud with the
+                     given cell size instead of ICP
+  --crop FILE      only use the points of both clouds within the regions of a GeoJSON or WKT file";
+
+fn exit_with(msg: &str) -> ! {
+    eprintln!("{}\n\n{}", msg, USAGE);
+    process::exit(1)
 }
 
-impl Default for Parameters {
-    fn default() -> Self {
-        Parameters {
-            max_overlap_distance: 1.0,
-            correspondences: 1000,
-            neighbors: 10,
-            max_iterations: 100,
-            min_planarity: 10,
-        }
-    }
-}
-
-
 fn main() {
-    const FILE1: &str = "bunny1.xyz";
-    const FILE2: &str = "bunny2.xyz";
-
-    let params = Parameters::default();
-    let mut fixed = PointCloud::read_from_xyz(FILE1);
-    let mut moved = PointCloud::read_from_xyz(FILE2);
-
-    if params.max_overlap_distance > 0.0 {
-        print!("Consider partial overlap of point clouds ...\n");
-        fixed.select_in_range(&mut moved, params.max_overlap_distance);
-        if fixed.selection_idx().len() == 0 {
-            panic!(
-                "Point clouds do not overlap within max_overlap_distance = {}. \
-            Consider increasing the value of max_overlap_distance.",
-                params.max_overlap_distance
-            );
+    let mut files: Vec<String> = Vec::new();
+    let mut params = Parameters {
+        debug_dir: Some(".".to_string()),
+        ..Parameters::default()
+    };
+
+    let mut color_weight = 0.03;
+    let mut max_color = 255.0;
+    let mut ndt: Option<NdtParameters> = None;
+    let mut crop_file: Option<String> = None;
+    // ICP options which have no effect on NDT
+    let mut icp_options: Vec<String> = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "--metric" => {
+                icp_options.push(arg.clone());
+                params.error_metric = match args.next().as_deref() {
+                    Some("point-to-plane") => ErrorMetric::PointToPlane,
+                    Some("point-to-point") => ErrorMetric::PointToPoint,
+                    Some("symmetric") => ErrorMetric::Symmetric,
+                    Some("gicp") => ErrorMetric::Gicp,
+                    Some("colored") => ErrorMetric::Colored { color_weight },
+                    _ => exit_with("Expected point-to-plane, point-to-point, symmetric, gicp or colored after --metric"),
+                };
+            }
+            "--color-weight" => {
+                icp_options.push(arg.clone());
+                color_weight = args.next()
+                    .and_then(|w| w.parse().ok())
+                    .filter(|w| (0.0..=1.0).contains(w))
+                    .unwrap_or_else(|| exit_with("Expected a weight in [0, 1] after --color-weight"));
+            }
+            "--color-max" => {
+                icp_options.push(arg.clone());
+                max_color = args.next()
+                    .and_then(|m| m.parse().ok())
+                    .filter(|m: &f64| *m > 0.0)
+                    .unwrap_or_else(|| exit_with("Expected a value > 0 after --color-max"));
+            }
+            "--ndt" => {
+                let resolution = args.next()
+                    .and_then(|r| r.parse().ok())
+                    .filter(|r: &f64| *r > 0.0)
+                    .unwrap_or_else(|| exit_with("Expected a resolution > 0 after --ndt"));
+                ndt = Some(NdtParameters { resolution, ..NdtParameters::default() });
+            }
+            "--reciprocal" => {
+                icp_options.push(arg.clone());
+                params.reciprocal = true;
+            }
+            "--reject" => {
+                icp_options.push(arg.clone());
+                let spec = args.next().unwrap_or_else(|| exit_with("Missing value of --reject"));
+                params.rejection = RejectionPipeline::parse(&spec).unwrap_or_else(|e| exit_with(&e));
+            }
+            "--crop" => {
+                crop_file = Some(args.next().unwrap_or_else(|| exit_with("Missing file after --crop")));
+            }
+            _ if arg.starts_with('-') => exit_with(&format!("Unknown option '{}'", arg)),
+            _ => files.push(arg),
         }
-        PointCloud::write_to_file(fixed.selection(), "initial_selection.xyz");
+    }
+    let (file1, file2) = match files.as_slice() {
+        [] => ("bunny1.xyz", "bunny2.xyz"),
+        [file1, file2] => (file1.as_str(), file2.as_str()),
+        _ => exit_with("Expected either no or two point cloud files"),
+    };
+
+    if ndt.is_some() && !icp_options.is_empty() {
+        exit_with(&format!("--ndt cannot be combined with {}", icp_options.join(", ")));
     }
 
-
-    print!("Select points for correspondences in fixed point cloud ...\n");
-    fixed.select_n_pts(params.correspondences);
-    PointCloud::write_to_file(fixed.selection(), &*format!("select_{}_pts.xyz", params.correspondences));
-
-    println!("Estimate normals of selected points ...\n");
-    fixed.estimate_normals(params.neighbors);
-
-
-    // ToDo: Needed later on for matching
-    /*
-    let h_old: Array2<f64> = Array::eye(4);
-    let h_new: Array2<f64> = Array::from_elem((4, 4), 0.);
-    let d_h: Array2<f64> = Array::from_elem((4, 4), 0.);
-    */
-
-
-    println!("Start iterations ...\n");
-    for i in 0..params.max_iterations {
-        println!("Iteration {}:", i);
-
-        let now = Instant::now();
-        let mut dist_res = PointCloud::cloud_to_cloud_distance(&fixed.selection(), &moved.selection());
-        println!("\tdist_between_neighbors took: {}[ms]", now.elapsed().as_millis());
-
-        let now = Instant::now();
-        let valid_idx = reject(&fixed.selection(), &mut dist_res, params.min_planarity);
-        let fixed_valid = PointCloud::select_from_cloud(fixed.selection(), &valid_idx);
-
-        let moved_valid_idx: Vec<usize> = valid_idx.iter().map(|idx| dist_res.nn[*idx][0].idx).collect();
-        let moved_valid = PointCloud::select_from_cloud(moved.selection(), &moved_valid_idx);
-        assert_eq!(fixed_valid.point_amount(), moved_valid.point_amount());
-
-        println!("\tRemaining points for rigid-body transformation: {}", fixed_valid.point_amount());
-        println!("\tRejecting and filtering took: {}[ms]", now.elapsed().as_millis());
-
-        let now = Instant::now();
-        estimate_rigid_body_transformation(&fixed_valid, &moved_valid);
-        println!("\tEstimating rigid-body transformation took: {}[ms]\n", now.elapsed().as_millis());
+    if let ErrorMetric::Colored { .. } = params.error_metric {
+        // The weight may be given after the metric
+        params.error_metric = ErrorMetric::Colored { color_weight };
+    }
+    let read = |file: &str| if params.error_metric.needs_colors() {
+        PointCloud::read_from_xyzrgb(file, max_color)
+    } else {
+        PointCloud::read_from_xyz(file)
+    };
+    let mut fixed = read(file1);
+    let mut moved = read(file2);
+    if let Some(path) = &crop_file {
+        let regions = read_regions(path).unwrap_or_else(|e| exit_with(&e));
+        fixed.crop(&regions);
+        moved.crop(&regions);
     }
+
+    let result = match &ndt {
+        Some(ndt) => register_ndt(&fixed, &mut moved, ndt),
+        None => register(&mut fixed, &mut moved, &params),
+    };
+    println!("Sampling: {:?}, converged: {} after {} iterations",
+             result.sampling, result.converged, result.iterations);
 }
@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use linfa_linalg::eigh::EighInto;
+use ndarray::{Array, Array1, Array2, s};
+
+use crate::cloud_view::CloudView;
+use crate::eigen3::{Covariance3, SymmetricEigen3};
+use crate::icp::RegistrationResult;
+use crate::pointcloud::PointCloud;
+use crate::rigid_body_transformation::{
+    euler_angles_to_rotation_matrix, homogeneous_transformation_matrix, point_to_point_rows,
+    rotation_matrix_to_euler_angles,
+};
+use crate::sampling::SamplingStrategy;
+
+// Cells with fewer points don't get a Gaussian
+const MIN_CELL_POINTS: usize = 5;
+// Eigenvalues of the cell covariances are bounded from below by this fraction of the largest one
+const MIN_EIGENVALUE_RATIO: f64 = 0.01;
+
+#[derive(Clone, Debug)]
+pub struct NdtParameters {
+    // Edge length of the cells of the fixed cloud
+    pub resolution: f64,
+    // Expected fraction of points of the moved cloud without counterpart in the fixed cloud
+    pub outlier_ratio: f64,
+    // Number of points of the moved cloud which are used
+    pub points: usize,
+    pub sampling: SamplingStrategy,
+    // Neighbors for the normal estimation of the moved points, if needed by the sampling strategy
+    pub neighbors: usize,
+    pub max_iterations: usize,
+    // Iteration stops if the parameters change less than this (in radian and units of the points)
+    pub min_step: f64,
+}
+
+impl Default for NdtParameters {
+    fn default() -> Self {
+        NdtParameters {
+            resolution: 1.0,
+            outlier_ratio: 0.55,
+            points: 5000,
+            sampling: SamplingStrategy::Stride,
+            neighbors: 10,
+            max_iterations: 35,
+            min_step: 1e-6,
+        }
+    }
+}
+
+// Normal distribution of the points of a cell
+struct Cell {
+    mean: [f64; 3],
+    inv_covariance: [[f64; 3]; 3],
+}
+
+/// Normal Distributions Transform of a point cloud, i.e. one Gaussian per occupied cell.
+pub struct NdtGrid {
+    resolution: f64,
+    cells: HashMap<[i64; 3], Cell>,
+}
+
+impl NdtGrid {
+    pub fn new(view: &CloudView, resolution: f64) -> NdtGrid {
+        assert!(resolution > 0.0, "resolution must be > 0");
+        let mut members: HashMap<[i64; 3], Vec<[f64; 3]>> = HashMap::new();
+        for p in view.points() {
+            members.entry(Self::key(&p, resolution)).or_default().push(p);
+        }
+
+        let cells = members.into_iter()
+            .filter(|(_, points)| points.len() >= MIN_CELL_POINTS)
+            .map(|(key, points)| {
+                let mut covariance = Covariance3::new(points[0]);
+                points.iter().for_each(|p| covariance.add(p));
+                let eigen = SymmetricEigen3::new(&covariance.covariance(1.0));
+                let min_value = MIN_EIGENVALUE_RATIO * eigen.values[0];
+                let values = eigen.values.map(|v| v.max(min_value).max(f64::MIN_POSITIVE));
+                let inv_covariance = [0, 1, 2].map(|r| [0, 1, 2].map(|c| {
+                    (0..3).map(|k| eigen.vectors[k][r] / values[k] * eigen.vectors[k][c]).sum()
+                }));
+                let cell = Cell { mean: covariance.mean(), inv_covariance };
+                (key, cell)
+            })
+            .collect();
+        NdtGrid { resolution, cells }
+    }
+
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    fn key(p: &[f64; 3], resolution: f64) -> [i64; 3] {
+        p.map(|coord| (coord / resolution).floor() as i64)
+    }
+
+    // Cells of the 3x3x3 neighborhood whose mean is within resolution of p
+    fn near_cells(&self, p: &[f64; 3]) -> impl Iterator<Item=&Cell> + '_ {
+        let key = Self::key(p, self.resolution);
+        let p = *p;
+        (0..27)
+            .filter_map(move |k| {
+                let offset = [k / 9 - 1, (k / 3) % 3 - 1, k % 3 - 1];
+                self.cells.get(&[key[0] + offset[0], key[1] + offset[1], key[2] + offset[2]])
+            })
+            .filter(move |cell| (0..3).map(|i| (cell.mean[i] - p[i]).powi(2)).sum::<f64>() <= self.resolution.powi(2))
+    }
+}
+
+// Constants d1, d2 of the Gaussian approximation of the mixture of a normal and a uniform
+// distribution (Magnusson 2009, eq. 6.8)
+fn score_constants(outlier_ratio: f64, resolution: f64) -> (f64, f64) {
+    let c1 = 10.0 * (1.0 - outlier_ratio);
+    let c2 = outlier_ratio / resolution.powi(3);
+    let d3 = -c2.ln();
+    let d1 = -(c1 + c2).ln() - d3;
+    let d2 = -2.0 * ((-(c1 * (-0.5_f64).exp() + c2).ln() - d3) / d1).ln();
+    (d1, d2)
+}
+
+fn mat_vec(m: &[[f64; 3]; 3], v: &[f64; 3]) -> [f64; 3] {
+    m.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+fn dot(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+// Second derivatives of the rotated point p w.r.t. the Euler angles at zero, i.e. K_i * K_j * p with
+// K_i the cross product matrix of the i-th axis (R = Rx * Ry * Rz, so the order matters)
+fn second_derivatives(p: &[f64; 3]) -> [[[f64; 3]; 3]; 3] {
+    let cross = |axis: usize, v: [f64; 3]| -> [f64; 3] {
+        match axis {
+            0 => [0.0, -v[2], v[1]],
+            1 => [v[2], 0.0, -v[0]],
+            _ => [-v[1], v[0], 0.0],
+        }
+    };
+    [0, 1, 2].map(|i| [0, 1, 2].map(|j| {
+        let (first, second) = if i <= j { (i, j) } else { (j, i) };
+        cross(first, cross(second, *p))
+    }))
+}
+
+// Score (to be minimized), gradient and Hessian w.r.t. alpha1, alpha2, alpha3, tx, ty, tz at zero
+fn score(grid: &NdtGrid, points: &[[f64; 3]], (d1, d2): (f64, f64), derivatives: bool) -> (f64, Array1<f64>, Array2<f64>) {
+    let mut value = 0.0;
+    let mut gradient: Array1<f64> = Array1::zeros(6);
+    let mut hessian: Array2<f64> = Array2::zeros((6, 6));
+    for p in points {
+        let jacobian = point_to_point_rows(*p);
+        let second = if derivatives { Some(second_derivatives(p)) } else { None };
+        for cell in grid.near_cells(p) {
+            let q = [0, 1, 2].map(|i| p[i] - cell.mean[i]);
+            let c_q = mat_vec(&cell.inv_covariance, &q);
+            let e = (-d2 / 2.0 * dot(&q, &c_q)).exp();
+            value += d1 * e;
+            let Some(second) = &second else { continue };
+
+            // Derivatives of the point w.r.t. parameter i are the columns of the jacobian
+            let column = |i: usize| [jacobian[0][i], jacobian[1][i], jacobian[2][i]];
+            let g: [f64; 6] = [0, 1, 2, 3, 4, 5].map(|i| dot(&c_q, &column(i)));
+            let factor = -d1 * d2 * e;
+            for i in 0..6 {
+                gradient[i] += factor * g[i];
+                for j in 0..6 {
+                    let mut h = -d2 * g[i] * g[j] + dot(&column(j), &mat_vec(&cell.inv_covariance, &column(i)));
+                    if i < 3 && j < 3 {
+                        h += dot(&c_q, &second[i][j]);
+                    }
+                    hessian[[i, j]] += factor * h;
+                }
+            }
+        }
+    }
+    (value, gradient, hessian)
+}
+
+fn transform_points(points: &[[f64; 3]], h: &Array2<f64>) -> Vec<[f64; 3]> {
+    points.iter()
+        .map(|p| [0, 1, 2].map(|r| h[[r, 0]] * p[0] + h[[r, 1]] * p[1] + h[[r, 2]] * p[2] + h[[r, 3]]))
+        .collect()
+}
+
+fn parameters_to_matrix(x: &Array1<f64>) -> Array2<f64> {
+    homogeneous_transformation_matrix(&euler_angles_to_rotation_matrix(x[0], x[1], x[2]), &x.slice(s![3..]).to_owned())
+}
+
+// Registers the moved onto the fixed point cloud by maximizing the likelihood of the (selected)
+// moved points w.r.t. the Normal Distributions Transform of the (selected) fixed points with
+// Newton's method. The uncertainties are standard deviations (in radian and units of the points)
+// from the inverse Hessian of the score at the solution, the condition number is the ratio of the
+// largest to smallest eigenvalue of the Hessian (both NaN if the Hessian is not positive definite,
+// i.e. the solution is no minimum). No correspondences are used, so the diagnostics are empty.
+pub fn register_ndt(fixed: &PointCloud, moved: &mut PointCloud, params: &NdtParameters) -> RegistrationResult {
+    let now = Instant::now();
+    let grid = NdtGrid::new(&fixed.selection(), params.resolution);
+    if grid.is_empty() {
+        panic!("No cell of the fixed point cloud contains {} points. Consider increasing the resolution.",
+               MIN_CELL_POINTS);
+    }
+    println!("NDT grid with {} cells took: {}", grid.len(), now.elapsed().as_millis());
+
+    let initial_selection = moved.selection_idx().to_vec();
+    if let SamplingStrategy::NormalSpace { .. } | SamplingStrategy::Stability = params.sampling {
+        println!("Estimate normals of moved points for {:?} sampling ...", params.sampling);
+        moved.estimate_normals(params.neighbors);
+    }
+    moved.select_n_pts_with(params.points, &params.sampling);
+    let mut points: Vec<[f64; 3]> = moved.selection().points().collect();
+    moved.set_selection(&initial_selection);
+
+    let constants = score_constants(params.outlier_ratio, params.resolution);
+    let mut h: Array2<f64> = Array::eye(4);
+    let mut iterations = 0;
+    let mut converged = false;
+
+    println!("{:>9} | {:>15} | {:>15} | {:>9}", "Iteration", "score", "step", "time[ms]");
+    for i in 0..params.max_iterations {
+        let now = Instant::now();
+        let (value, gradient, hessian) = score(&grid, &points, constants, true);
+
+        // Newton step, the Hessian is made positive definite by taking the absolute eigenvalues
+        let (values, vectors) = hessian.eigh_into().expect("Could not calculate eigen decomposition");
+        let max_value = values.fold(0.0_f64, |max, v| max.max(v.abs()));
+        if max_value == 0.0 {
+            panic!("No point of the moved point cloud lies close to a cell of the fixed point cloud.");
+        }
+        let inv_values = values.mapv(|v| 1.0 / v.abs().max(1e-12 * max_value));
+        let mut step: Array1<f64> = -vectors.dot(&(&vectors.t().dot(&gradient) * &inv_values));
+
+        // Backtracking, as the step may overshoot far from the optimum
+        let mut dh = parameters_to_matrix(&step);
+        let mut improved = false;
+        for _ in 0..10 {
+            if score(&grid, &transform_points(&points, &dh), constants, false).0 <= value {
+                improved = true;
+                break;
+            }
+            step /= 2.0;
+            dh = parameters_to_matrix(&step);
+        }
+        if !improved {
+            // Even tiny steps increase the score, so the current pose is (numerically) optimal
+            println!("{:>9} | {:>15.4} | {:>15} | {:>9}", i + 1, value, "-", now.elapsed().as_millis());
+            println!("No step decreases the score -> stop iteration!");
+            converged = true;
+            break;
+        }
+        points = transform_points(&points, &dh);
+        h = dh.dot(&h);
+        iterations = i + 1;
+
+        let step_size = step.fold(0.0_f64, |max, v| max.max(v.abs()));
+        println!("{:>9} | {:>15.4} | {:>15.2e} | {:>9}", iterations, value, step_size, now.elapsed().as_millis());
+        if step_size < params.min_step {
+            println!("Convergence criteria fulfilled -> stop iteration!");
+            converged = true;
+            break;
+        }
+    }
+    moved.transform(&h);
+
+    // Near the optimum, the score is -d1 * d2 times the sum of the squared Mahalanobis distances of
+    // the points to their cells (halved), i.e. the negative log-likelihood of the parameters. Hence
+    // -d1 * d2 times the inverse Hessian of the score approximates the covariance of the parameters.
+    let (_, _, hessian) = score(&grid, &points, constants, true);
+    let (values, vectors) = hessian.eigh_into().expect("Could not calculate eigen decomposition");
+    let (min_value, max_value) = values.fold((f64::INFINITY, 0.0_f64), |(lo, hi), v| (lo.min(*v), hi.max(*v)));
+    let (uncertainties, condition_number) = if min_value > 0.0 {
+        let (d1, d2) = constants;
+        let variances: Array1<f64> = vectors.mapv(|v| v * v).dot(&values.mapv(|v| -d1 * d2 / v));
+        ([0, 1, 2, 3, 4, 5].map(|i| variances[i].sqrt()), max_value / min_value)
+    } else {
+        ([f64::NAN; 6], f64::NAN)
+    };
+
+    println!("Estimated transformation matrix H:");
+    for row in h.outer_iter() {
+        println!("[{:12.6} {:12.6} {:12.6} {:12.6}]", row[0], row[1], row[2], row[3]);
+    }
+    println!("Condition number of the Hessian: {:.1}", condition_number);
+
+    let angles = rotation_matrix_to_euler_angles(&h.slice(s![..3, ..3]).to_owned());
+    RegistrationResult {
+        parameters: [angles[0], angles[1], angles[2], h[[0, 3]], h[[1, 3]], h[[2, 3]]],
+        h,
+        uncertainties,
+        condition_number,
+        iterations,
+        converged,
+        sampling: params.sampling.clone(),
+        diagnostics: Vec::new(),
+    }
+}
+
+
+#[cfg(test)]
+mod ndt_test {
+    use ndarray::{array, Array1, Array2, s};
+
+    use crate::icp::icp_test::surface;
+    use crate::ndt::{NdtParameters, register_ndt};
+    use crate::rigid_body_transformation::{euler_angles_to_rotation_matrix, homogeneous_transformation_matrix};
+    use crate::sampling::SamplingStrategy;
+
+    #[test]
+    fn register_ndt_recovers_known_transformation() {
+        let r = euler_angles_to_rotation_matrix(0.02, -0.01, 0.03);
+        let t = array![0.1, -0.05, 0.03];
+        let h_true = homogeneous_transformation_matrix(&r, &t);
+        let t_inv: Array1<f64> = -r.t().dot(&t);
+        let h_inv = homogeneous_transformation_matrix(&r.t().to_owned(), &t_inv);
+
+        let variants = [
+            NdtParameters { resolution: 0.5, ..NdtParameters::default() },
+            // Needs normals of the moved points, which are estimated first
+            NdtParameters {
+                resolution: 0.5,
+                points: 1500,
+                sampling: SamplingStrategy::NormalSpace { bins: 4 },
+                ..NdtParameters::default()
+            },
+        ];
+        for params in variants {
+            let fixed = surface();
+            let mut moved = surface();
+            moved.transform(&h_inv);
+
+            let res = register_ndt(&fixed, &mut moved, &params);
+            assert!(res.converged);
+            let should_be_zero: Array2<f64> = &res.h - &h_true;
+            assert!(should_be_zero.slice(s![..3, ..]).iter().all(|v| v.abs() < 1e-3));
+            // Standard deviations of the angles (in radian) and of the translation
+            assert!(res.uncertainties.iter().all(|u| u.is_finite() && *u > 0.0 && *u < 0.01), "{:?}", res.uncertainties);
+            assert!(res.condition_number.is_finite());
+        }
+    }
+}
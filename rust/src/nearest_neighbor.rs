@@ -2,19 +2,44 @@ use std::fmt::{Display, Formatter};
 
 use kdtree::distance::squared_euclidean;
 use kdtree::KdTree;
-use ndarray::Array1;
+use rayon::prelude::*;
 
+use crate::cloud_view::CloudView;
+use crate::eigen3::SymmetricEigen3;
+use crate::features::GeometricFeature;
 use crate::pointcloud::PointCloud;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct NormalRes {
-    pub eigenvector: Array1<f64>,
+    pub eigenvector: [f64; 3],
+    // Eigenvalues of the local covariance matrix, sorted in descending order
+    pub eigenvalues: [f64; 3],
     pub planarity: f64,
+    // Fraction of the neighbors which are consistent with the fitted plane
+    pub inlier_ratio: f64,
+}
+
+impl NormalRes {
+    pub fn from_covariance(covariance: &[[f64; 3]; 3]) -> NormalRes {
+        let eigen = SymmetricEigen3::new(covariance);
+        let eig_vals = eigen.values;
+        NormalRes {
+            eigenvector: eigen.vectors[2],
+            eigenvalues: eig_vals,
+            planarity: (eig_vals[1] - eig_vals[2]) / eig_vals[0],
+            // Unknown without the residuals of the neighbors, see NormalEstimator
+            inlier_ratio: f64::NAN,
+        }
+    }
+
+    pub fn eigenentropy(&self) -> f64 {
+        GeometricFeature::Eigenentropy.from_eigenvalues(&self.eigenvalues)
+    }
 }
 
 pub struct NNRes {
-    pub(crate) distance: f64,
-    pub(crate) idx: usize,
+    pub distance: f64,
+    pub idx: usize,
 }
 
 impl From<(f64, usize)> for NNRes {
@@ -25,27 +50,94 @@ impl From<(f64, usize)> for NNRes {
 
 impl Display for NormalRes {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "NormalRes:\nEigenvector: {}\nPlanarity:{}", self.eigenvector, self.planarity)
+        write!(f, "NormalRes:\nEigenvector: {:?}\nPlanarity:{}", self.eigenvector, self.planarity)
     }
 }
 
-pub fn knn_search(
-    reference: &PointCloud,
-    query: &PointCloud,
-    k: usize,
-) -> Vec<Vec<NNRes>> {
-    let mut kdtree = KdTree::new(3);
+/// Spatial index used for nearest neighbor queries during correspondence search.
+pub trait SpatialIndex: Sync {
+    /// Returns the `k` nearest neighbors of `query`, sorted by ascending (squared) distance.
+    fn nearest(&self, query: &[f64; 3], k: usize) -> Vec<NNRes>;
+
+    /// Returns the coordinates of the indexed point `idx`.
+    fn point(&self, idx: usize) -> [f64; 3];
+}
 
-    for (idx, p) in reference.points().outer_iter().enumerate() {
-        kdtree.add([p[[0]], p[[1]], p[[2]]], idx).expect("Could not add point to kdtree");
+/// Static kd-tree over the points of a point cloud (or of a view on it). Neighbors are returned by
+/// their original index into the cloud.
+pub struct KdTreeIndex<'a> {
+    cloud: &'a PointCloud,
+    kdtree: KdTree<f64, usize, [f64; 3]>,
+}
+
+impl<'a> KdTreeIndex<'a> {
+    pub fn new(cloud: &'a PointCloud) -> KdTreeIndex<'a> {
+        Self::from_view(&cloud.view())
+    }
+
+    pub fn from_view(view: &CloudView<'a>) -> KdTreeIndex<'a> {
+        let mut kdtree = KdTree::new(3);
+
+        for (i, p) in view.points().enumerate() {
+            kdtree.add(p, view.original_idx(i)).expect("Could not add point to kdtree");
+        }
+        KdTreeIndex { cloud: view.cloud(), kdtree }
+    }
+}
+
+impl KdTreeIndex<'_> {
+    /// Returns all neighbors within `radius` of `query`, sorted by ascending (squared) distance.
+    pub fn within(&self, query: &[f64; 3], radius: f64) -> Vec<NNRes> {
+        self.kdtree.within(query, radius * radius, &squared_euclidean)
+            .expect("Could not fetch neighbors within radius for point")
+            .iter()
+            .map(|entry| NNRes::from((entry.0, *entry.1)))
+            .collect()
     }
+}
 
-    query.points().outer_iter().map(|q| {
-        kdtree.nearest(&[q[[0]], q[[1]], q[[2]]], k, &squared_euclidean)
+impl SpatialIndex for KdTreeIndex<'_> {
+    fn nearest(&self, query: &[f64; 3], k: usize) -> Vec<NNRes> {
+        self.kdtree.nearest(query, k, &squared_euclidean)
             .expect("Could not fetch nn for point")
             .iter()
             .map(|entry| NNRes::from((entry.0, *entry.1)))
             .collect()
+    }
+
+    fn point(&self, idx: usize) -> [f64; 3] {
+        let p = self.cloud.points();
+        [p[[idx, 0]], p[[idx, 1]], p[[idx, 2]]]
+    }
+}
+
+pub fn knn_search(
+    reference: &CloudView,
+    query: &CloudView,
+    k: usize,
+) -> Vec<Vec<NNRes>> {
+    knn_search_in(&KdTreeIndex::from_view(reference), query, k)
+}
+
+pub fn knn_search_in<I: SpatialIndex>(
+    index: &I,
+    query: &CloudView,
+    k: usize,
+) -> Vec<Vec<NNRes>> {
+    (0..query.len()).into_par_iter().map(|i| {
+        index.nearest(&query.point(i), k)
+    })
+        .collect()
+}
+
+pub fn radius_search(
+    reference: &CloudView,
+    query: &CloudView,
+    radius: f64,
+) -> Vec<Vec<NNRes>> {
+    let index = KdTreeIndex::from_view(reference);
+    (0..query.len()).into_par_iter().map(|i| {
+        index.within(&query.point(i), radius)
     })
         .collect()
-}
\ No newline at end of file
+}
@@ -0,0 +1,182 @@
+use ndarray::{Array1, Array2, ArrayView1};
+use rand::seq::index::sample;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use crate::eigen3::Covariance3;
+use crate::nearest_neighbor::NormalRes;
+use crate::stats::{MAD_TO_SIGMA, median};
+
+// Tuning constant of Tukey's biweight function (95% efficiency for Gaussian residuals)
+const TUKEY_C: f64 = 4.685;
+// Residuals within this multiple of sigma_mad are counted as inliers
+const INLIER_SIGMA: f64 = 2.5;
+
+/// Method used to fit the local plane of a point to its neighbors.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NormalEstimator {
+    /// Plain covariance PCA.
+    Pca,
+    /// PCA which is iteratively reweighted by Tukey's biweight of the point-to-plane residuals.
+    ReweightedPca { iterations: usize },
+    /// RANSAC plane fit (refined by PCA over the inliers). `seed` makes the result reproducible.
+    Ransac { iterations: usize, threshold: f64, seed: u64 },
+    /// PCA with Gaussian weights w.r.t. the distance of the neighbors to the query point.
+    DistanceWeighted { sigma: f64 },
+}
+
+impl NormalEstimator {
+    /// Fits a plane to `neighbors` (one point per row). `sq_dists` are the squared distances of the
+    /// neighbors to the query point with index `point_idx`.
+    pub fn fit(&self, neighbors: &Array2<f64>, sq_dists: &[f64], point_idx: usize) -> NormalRes {
+        match *self {
+            NormalEstimator::Pca => {
+                let normal = weighted_pca(neighbors, &vec![1.0; neighbors.nrows()]);
+                with_inlier_ratio(normal, neighbors, None)
+            }
+            NormalEstimator::ReweightedPca { iterations } => {
+                let mut weights = vec![1.0; neighbors.nrows()];
+                let mut normal = weighted_pca(neighbors, &weights);
+                for _ in 0..iterations {
+                    let residuals = residuals(neighbors, &normal);
+                    let c = TUKEY_C * sigma_mad(&residuals, extent(neighbors.iter().copied()));
+                    weights = residuals.iter().map(|r| {
+                        if r.abs() < c { (1.0 - (r / c).powi(2)).powi(2) } else { 0.0 }
+                    }).collect();
+                    if weights.iter().filter(|w| **w > 0.0).count() < 3 {
+                        break;
+                    }
+                    normal = weighted_pca(neighbors, &weights);
+                }
+                with_inlier_ratio(normal, neighbors, None)
+            }
+            NormalEstimator::Ransac { iterations, threshold, seed } => {
+                let mut rng = ChaCha8Rng::seed_from_u64(seed);
+                // Independent stream per point, so that the result does not depend on the point order
+                rng.set_stream(point_idx as u64);
+
+                let mut best_inliers = vec![true; neighbors.nrows()];
+                let mut best_count = 0;
+                for _ in 0..iterations {
+                    let candidates = sample(&mut rng, neighbors.nrows(), 3);
+                    let p: Vec<ArrayView1<f64>> = candidates.iter().map(|i| neighbors.row(i)).collect();
+                    let n = cross(&(&p[1] - &p[0]), &(&p[2] - &p[0]));
+                    let norm = n.dot(&n).sqrt();
+                    if norm <= f64::EPSILON {
+                        continue;
+                    }
+                    let n = n / norm;
+                    let inliers: Vec<bool> = neighbors.outer_iter()
+                        .map(|x| (&x - &p[0]).dot(&n).abs() <= threshold)
+                        .collect();
+                    let count = inliers.iter().filter(|inlier| **inlier).count();
+                    if count > best_count {
+                        best_count = count;
+                        best_inliers = inliers;
+                    }
+                }
+
+                let weights: Vec<f64> = best_inliers.iter()
+                    .map(|inlier| if *inlier { 1.0 } else { 0.0 })
+                    .collect();
+                let normal = weighted_pca(neighbors, &weights);
+                with_inlier_ratio(normal, neighbors, Some(threshold))
+            }
+            NormalEstimator::DistanceWeighted { sigma } => {
+                let weights: Vec<f64> = sq_dists.iter().map(|d| (-d / (2.0 * sigma * sigma)).exp()).collect();
+                let normal = weighted_pca(neighbors, &weights);
+                with_inlier_ratio(normal, neighbors, None)
+            }
+        }
+    }
+}
+
+fn weighted_pca(neighbors: &Array2<f64>, weights: &[f64]) -> (NormalRes, [f64; 3]) {
+    let row = |i: usize| [neighbors[[i, 0]], neighbors[[i, 1]], neighbors[[i, 2]]];
+    let mut covariance = Covariance3::new(row(0));
+    for (i, w) in weights.iter().enumerate() {
+        covariance.add_weighted(&row(i), *w);
+    }
+    (NormalRes::from_covariance(&covariance.covariance(0.0)), covariance.mean())
+}
+
+fn residuals(neighbors: &Array2<f64>, (normal, mean): &(NormalRes, [f64; 3])) -> Vec<f64> {
+    let n = normal.eigenvector;
+    neighbors.outer_iter()
+        .map(|x| (x[0] - mean[0]) * n[0] + (x[1] - mean[1]) * n[1] + (x[2] - mean[2]) * n[2])
+        .collect()
+}
+
+// Largest absolute coordinate of the neighbors (at least 1), the scale of the rounding errors
+pub(crate) fn extent(coordinates: impl IntoIterator<Item=f64>) -> f64 {
+    coordinates.into_iter().fold(0.0_f64, |max, x| max.max(x.abs())).max(1.0)
+}
+
+// Robust standard deviation of the residuals, bounded from below to cope with perfect planes
+fn sigma_mad(residuals: &[f64], extent: f64) -> f64 {
+    let abs_res: Vec<f64> = residuals.iter().map(|r| r.abs()).collect();
+    let mad = median(&abs_res);
+    (MAD_TO_SIGMA * mad).max(1e-12 * extent)
+}
+
+// Fraction of the point-to-plane residuals within threshold (default: INLIER_SIGMA * sigma_mad)
+pub(crate) fn inlier_ratio(residuals: &[f64], extent: f64, threshold: Option<f64>) -> f64 {
+    let threshold = threshold.unwrap_or_else(|| INLIER_SIGMA * sigma_mad(residuals, extent));
+    let inliers = residuals.iter().filter(|r| r.abs() <= threshold).count();
+    inliers as f64 / residuals.len() as f64
+}
+
+fn with_inlier_ratio(fit: (NormalRes, [f64; 3]), neighbors: &Array2<f64>, threshold: Option<f64>) -> NormalRes {
+    let residuals = residuals(neighbors, &fit);
+    let (mut normal, _) = fit;
+    normal.inlier_ratio = inlier_ratio(&residuals, extent(neighbors.iter().copied()), threshold);
+    normal
+}
+
+fn cross(a: &Array1<f64>, b: &Array1<f64>) -> Array1<f64> {
+    Array1::from_vec(vec![
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ])
+}
+
+
+#[cfg(test)]
+mod normal_estimator_test {
+    use ndarray::{Array2, concatenate, Axis, array};
+
+    use crate::normal_estimator::NormalEstimator;
+
+    // Horizontal plane z = 0 plus three outliers of a second (vertical) surface
+    fn corner() -> Array2<f64> {
+        let mut plane = Vec::new();
+        for x in -2..=2 {
+            for y in -2..=2 {
+                plane.extend_from_slice(&[x as f64 * 0.1, y as f64 * 0.1, 0.0]);
+            }
+        }
+        let plane = Array2::from_shape_vec((25, 3), plane).unwrap();
+        let wall = array![[0.2, 0.0, 0.3], [0.2, 0.1, 0.4], [0.2, -0.1, 0.5]];
+        concatenate![Axis(0), plane, wall]
+    }
+
+    #[test]
+    fn robust_estimators_ignore_outliers() {
+        let neighbors = corner();
+        let sq_dists: Vec<f64> = neighbors.outer_iter().map(|p| p.dot(&p)).collect();
+
+        let pca = NormalEstimator::Pca.fit(&neighbors, &sq_dists, 0);
+        assert!(pca.eigenvector[2].abs() < 0.99);
+
+        let estimators = [
+            NormalEstimator::ReweightedPca { iterations: 10 },
+            NormalEstimator::Ransac { iterations: 50, threshold: 0.01, seed: 42 },
+        ];
+        for estimator in estimators {
+            let normal = estimator.fit(&neighbors, &sq_dists, 0);
+            assert_float_absolute_eq!(normal.eigenvector[2].abs(), 1.0, 1e-6);
+            assert_float_absolute_eq!(normal.inlier_ratio, 25.0 / 28.0, 1e-9);
+        }
+    }
+}
@@ -0,0 +1,439 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use ordered_float::OrderedFloat;
+
+use crate::nearest_neighbor::{NNRes, SpatialIndex};
+use crate::pointcloud::PointCloud;
+
+struct Node {
+    center: [f64; 3],
+    half_size: f64,
+    children: Option<[usize; 8]>,
+    points: Vec<usize>,
+    // Number of points stored below this node
+    count: usize,
+}
+
+impl Node {
+    fn leaf(center: [f64; 3], half_size: f64) -> Node {
+        Node { center, half_size, children: None, points: Vec::new(), count: 0 }
+    }
+
+    fn octant(&self, p: &[f64; 3]) -> usize {
+        (0..3).fold(0, |oct, axis| if p[axis] >= self.center[axis] { oct | (1 << axis) } else { oct })
+    }
+
+    fn child_center(&self, octant: usize) -> [f64; 3] {
+        let quarter = self.half_size / 2.0;
+        let mut c = self.center;
+        for (axis, coord) in c.iter_mut().enumerate() {
+            *coord += if octant & (1 << axis) != 0 { quarter } else { -quarter };
+        }
+        c
+    }
+
+    fn contains(&self, p: &[f64; 3]) -> bool {
+        (0..3).all(|axis| (p[axis] - self.center[axis]).abs() <= self.half_size)
+    }
+
+    fn intersects_box(&self, min: &[f64; 3], max: &[f64; 3]) -> bool {
+        (0..3).all(|axis| {
+            self.center[axis] + self.half_size >= min[axis] && self.center[axis] - self.half_size <= max[axis]
+        })
+    }
+
+    // Squared distance between a point and the cell of this node (0 if inside)
+    fn squared_distance(&self, p: &[f64; 3]) -> f64 {
+        (0..3).map(|axis| {
+            let d = (p[axis] - self.center[axis]).abs() - self.half_size;
+            if d > 0.0 { d * d } else { 0.0 }
+        }).sum()
+    }
+}
+
+/// Incrementally updatable octree, e.g. for a reference map which grows by every registered scan.
+///
+/// Leaves are split as soon as they hold more than `leaf_capacity` points. Leaves which reached
+/// the minimal cell size are never split - points inserted into such a full leaf are dropped, which
+/// caps the point density of the map. A leaf created by a split may receive all `leaf_capacity + 1`
+/// points of its parent; if it has the minimal size, it keeps them and accepts no further points.
+/// Non-finite points are rejected. Point ids stay valid until the point is removed; the ids
+/// (and nodes) of removed points are reused by later insertions.
+pub struct Octree {
+    nodes: Vec<Node>,
+    free_nodes: Vec<usize>,
+    root: Option<usize>,
+    points: Vec<Option<[f64; 3]>>,
+    free_ids: Vec<usize>,
+    leaf_capacity: usize,
+    min_half_size: f64,
+    len: usize,
+}
+
+//###############################
+//# 'Static' Octree methods     #
+//###############################
+impl Octree {
+    pub fn new(leaf_capacity: usize, min_leaf_size: f64) -> Octree {
+        assert!(leaf_capacity > 0, "leaf_capacity must be > 0");
+        assert!(min_leaf_size > 0.0, "min_leaf_size must be > 0");
+        Octree {
+            nodes: Vec::new(),
+            free_nodes: Vec::new(),
+            root: None,
+            points: Vec::new(),
+            free_ids: Vec::new(),
+            leaf_capacity,
+            min_half_size: min_leaf_size / 2.0,
+            len: 0,
+        }
+    }
+
+    pub fn from_cloud(cloud: &PointCloud, leaf_capacity: usize, min_leaf_size: f64) -> Octree {
+        let mut octree = Octree::new(leaf_capacity, min_leaf_size);
+        octree.insert_cloud(cloud);
+        octree
+    }
+}
+
+//###############################
+//# 'Getters' for Octree        #
+//###############################
+impl Octree {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, id: usize) -> Option<[f64; 3]> {
+        self.points.get(id).copied().flatten()
+    }
+
+    /// Iterates over all (id, point) pairs currently stored in the octree.
+    pub fn iter(&self) -> impl Iterator<Item=(usize, [f64; 3])> + '_ {
+        self.points.iter().enumerate().filter_map(|(id, p)| p.map(|p| (id, p)))
+    }
+}
+
+//###############################
+//#       Octree methods        #
+//###############################
+impl Octree {
+    /// Inserts a point and returns its id, or None if the point was dropped because its leaf is full
+    /// or because it has a non-finite coordinate.
+    pub fn insert(&mut self, p: [f64; 3]) -> Option<usize> {
+        // The root cell could never grow to contain NaN or infinite coordinates
+        if !p.iter().all(|coord| coord.is_finite()) {
+            return None;
+        }
+        let root = match self.root {
+            Some(root) => root,
+            None => {
+                let root = self.alloc(Node::leaf(p, self.min_half_size));
+                self.root = Some(root);
+                root
+            }
+        };
+        let root = self.grow_to_contain(root, &p);
+
+        let leaf = self.find_leaf(root, &p);
+        if self.nodes[leaf].points.len() >= self.leaf_capacity && !self.splittable(leaf) {
+            return None;
+        }
+
+        let id = match self.free_ids.pop() {
+            Some(id) => {
+                self.points[id] = Some(p);
+                id
+            }
+            None => {
+                self.points.push(Some(p));
+                self.points.len() - 1
+            }
+        };
+        self.len += 1;
+
+        let mut node = root;
+        while node != leaf {
+            self.nodes[node].count += 1;
+            node = self.nodes[node].children.expect("Path to leaf")[self.nodes[node].octant(&p)];
+        }
+        self.insert_into(leaf, id);
+        Some(id)
+    }
+
+    /// Inserts all points of a cloud and returns their ids (None for dropped points).
+    pub fn insert_cloud(&mut self, cloud: &PointCloud) -> Vec<Option<usize>> {
+        cloud.points()
+            .outer_iter()
+            .map(|p| self.insert([p[[0]], p[[1]], p[[2]]]))
+            .collect()
+    }
+
+    /// Removes all points within the axis-aligned box [min, max] and returns their ids.
+    pub fn remove_in_box(&mut self, min: [f64; 3], max: [f64; 3]) -> Vec<usize> {
+        let mut removed = Vec::new();
+        if let Some(root) = self.root {
+            self.remove_in_box_from(root, &min, &max, &mut removed);
+        }
+        self.len -= removed.len();
+        removed
+    }
+
+    // Stores a node, preferably in the slot of a released one
+    fn alloc(&mut self, node: Node) -> usize {
+        match self.free_nodes.pop() {
+            Some(idx) => {
+                self.nodes[idx] = node;
+                idx
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn splittable(&self, node: usize) -> bool {
+        self.nodes[node].half_size / 2.0 >= self.min_half_size
+    }
+
+    // Doubles the root cell towards p until p lies within the octree
+    fn grow_to_contain(&mut self, mut root: usize, p: &[f64; 3]) -> usize {
+        while !self.nodes[root].contains(p) {
+            let old = &self.nodes[root];
+            let mut center = old.center;
+            for (axis, coord) in center.iter_mut().enumerate() {
+                *coord += if p[axis] >= old.center[axis] { old.half_size } else { -old.half_size };
+            }
+            let mut new_root = Node::leaf(center, old.half_size * 2.0);
+            new_root.count = old.count;
+            let old_octant = new_root.octant(&old.center);
+
+            let mut children = [0; 8];
+            for (octant, child) in children.iter_mut().enumerate() {
+                if octant == old_octant {
+                    *child = root;
+                } else {
+                    *child = self.alloc(Node::leaf(new_root.child_center(octant), new_root.half_size / 2.0));
+                }
+            }
+            new_root.children = Some(children);
+            root = self.alloc(new_root);
+        }
+        self.root = Some(root);
+        root
+    }
+
+    fn find_leaf(&self, mut node: usize, p: &[f64; 3]) -> usize {
+        while let Some(children) = self.nodes[node].children {
+            node = children[self.nodes[node].octant(p)];
+        }
+        node
+    }
+
+    // Leaves of minimal size are not split, so they may exceed leaf_capacity by one point when
+    // they receive the points of a split parent
+    fn insert_into(&mut self, leaf: usize, id: usize) {
+        self.nodes[leaf].points.push(id);
+        self.nodes[leaf].count += 1;
+        if self.nodes[leaf].points.len() > self.leaf_capacity && self.splittable(leaf) {
+            self.split(leaf);
+        }
+    }
+
+    fn split(&mut self, node: usize) {
+        let mut children = [0; 8];
+        for (octant, child) in children.iter_mut().enumerate() {
+            *child = self.alloc(Node::leaf(self.nodes[node].child_center(octant), self.nodes[node].half_size / 2.0));
+        }
+        self.nodes[node].children = Some(children);
+
+        for id in std::mem::take(&mut self.nodes[node].points) {
+            let p = self.points[id].expect("Octree leaf references removed point");
+            let child = children[self.nodes[node].octant(&p)];
+            self.insert_into(child, id);
+        }
+    }
+
+    // Returns the number of points remaining below node
+    fn remove_in_box_from(&mut self, node: usize, min: &[f64; 3], max: &[f64; 3], removed: &mut Vec<usize>) -> usize {
+        if !self.nodes[node].intersects_box(min, max) {
+            return self.nodes[node].count;
+        }
+
+        match self.nodes[node].children {
+            None => {
+                let points = &mut self.points;
+                let free_ids = &mut self.free_ids;
+                self.nodes[node].points.retain(|id| {
+                    let p = points[*id].expect("Octree leaf references removed point");
+                    let inside = (0..3).all(|axis| p[axis] >= min[axis] && p[axis] <= max[axis]);
+                    if inside {
+                        points[*id] = None;
+                        free_ids.push(*id);
+                        removed.push(*id);
+                    }
+                    !inside
+                });
+                self.nodes[node].count = self.nodes[node].points.len();
+                self.nodes[node].count
+            }
+            Some(children) => {
+                let remaining: usize = children.iter()
+                    .map(|child| self.remove_in_box_from(*child, min, max, removed))
+                    .sum();
+
+                // Collapse children again if they fit into a single leaf
+                if remaining <= self.leaf_capacity {
+                    let mut ids = Vec::with_capacity(remaining);
+                    for child in children {
+                        self.release(child, &mut ids);
+                    }
+                    self.nodes[node].children = None;
+                    self.nodes[node].points = ids;
+                }
+                self.nodes[node].count = remaining;
+                remaining
+            }
+        }
+    }
+
+    // Moves the ids below node into ids and puts node and its descendants on the free list
+    fn release(&mut self, node: usize, ids: &mut Vec<usize>) {
+        match self.nodes[node].children.take() {
+            None => ids.append(&mut self.nodes[node].points),
+            Some(children) => children.iter().for_each(|child| self.release(*child, ids)),
+        }
+        self.free_nodes.push(node);
+    }
+}
+
+impl SpatialIndex for Octree {
+    // Best-first search: nodes are visited in order of their distance to the query point
+    fn nearest(&self, query: &[f64; 3], k: usize) -> Vec<NNRes> {
+        let mut best: BinaryHeap<(OrderedFloat<f64>, usize)> = BinaryHeap::with_capacity(k + 1);
+        let mut queue = BinaryHeap::new();
+        if let Some(root) = self.root {
+            queue.push(Reverse((OrderedFloat(self.nodes[root].squared_distance(query)), root)));
+        }
+
+        while let Some(Reverse((node_dist, node))) = queue.pop() {
+            if best.len() == k && best.peek().is_some_and(|(d, _)| node_dist > *d) {
+                break;
+            }
+            match self.nodes[node].children {
+                None => {
+                    for id in &self.nodes[node].points {
+                        let p = self.points[*id].expect("Octree leaf references removed point");
+                        let dist = OrderedFloat((0..3).map(|axis| (p[axis] - query[axis]).powi(2)).sum());
+                        if best.len() < k {
+                            best.push((dist, *id));
+                        } else if best.peek().is_some_and(|(d, _)| dist < *d) {
+                            best.pop();
+                            best.push((dist, *id));
+                        }
+                    }
+                }
+                Some(children) => {
+                    for child in children {
+                        queue.push(Reverse((OrderedFloat(self.nodes[child].squared_distance(query)), child)));
+                    }
+                }
+            }
+        }
+
+        best.into_sorted_vec()
+            .into_iter()
+            .map(|(dist, id)| NNRes::from((dist.0, id)))
+            .collect()
+    }
+
+    fn point(&self, idx: usize) -> [f64; 3] {
+        self.get(idx).expect("Point was removed from octree")
+    }
+}
+
+#[cfg(test)]
+mod octree_test {
+    use crate::nearest_neighbor::SpatialIndex;
+    use crate::octree::Octree;
+
+    fn grid() -> Vec<[f64; 3]> {
+        let mut points = Vec::new();
+        for x in 0..10 {
+            for y in 0..10 {
+                for z in 0..5 {
+                    points.push([x as f64 * 0.3, y as f64 * 0.2 - 1.0, z as f64 * 0.7]);
+                }
+            }
+        }
+        points
+    }
+
+    #[test]
+    fn nearest_matches_brute_force() {
+        let points = grid();
+        let mut octree = Octree::new(4, 0.01);
+        for p in &points {
+            assert!(octree.insert(*p).is_some());
+        }
+        assert_eq!(octree.len(), points.len());
+
+        let query = [1.05, 0.13, 1.4];
+        let nn = octree.nearest(&query, 5);
+
+        let mut expected: Vec<f64> = points.iter()
+            .map(|p| (0..3).map(|axis| (p[axis] - query[axis]).powi(2)).sum())
+            .collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        assert_eq!(nn.len(), 5);
+        for (res, dist) in nn.iter().zip(expected.iter()) {
+            assert_float_absolute_eq!(res.distance, *dist, 1e-12);
+        }
+    }
+
+    #[test]
+    fn remove_in_box_and_leaf_cap() {
+        let mut octree = Octree::new(2, 1.0);
+        assert_eq!(octree.insert([0.1, 0.1, 0.1]), Some(0));
+        assert_eq!(octree.insert([0.2, 0.2, 0.2]), Some(1));
+        // Leaf has reached minimal size and capacity
+        assert_eq!(octree.insert([0.15, 0.15, 0.15]), None);
+        assert_eq!(octree.insert([5.0, 5.0, 5.0]), Some(2));
+        assert_eq!(octree.insert([f64::NAN, 0.0, 0.0]), None);
+        assert_eq!(octree.insert([f64::INFINITY, 0.0, 0.0]), None);
+
+        let removed = octree.remove_in_box([0.0, 0.0, 0.0], [0.15, 0.15, 0.15]);
+        assert_eq!(removed, vec![0]);
+        assert_eq!(octree.len(), 2);
+        assert_eq!(octree.nearest(&[0.0, 0.0, 0.0], 1)[0].idx, 1);
+        // The id of the removed point is reused
+        assert_eq!(octree.insert([0.05, 0.05, 0.05]), Some(0));
+    }
+
+    #[test]
+    fn removal_releases_nodes_and_ids() {
+        let points = grid();
+        let mut octree = Octree::new(4, 0.01);
+        points.iter().for_each(|p| { octree.insert(*p); });
+        let nodes = octree.nodes.len();
+
+        // Remove and insert the same points twice - neither nodes nor point slots may pile up
+        for _ in 0..2 {
+            let removed = octree.remove_in_box([-10.0; 3], [10.0; 3]);
+            assert_eq!(removed.len(), points.len());
+            assert!(octree.is_empty());
+            points.iter().for_each(|p| { octree.insert(*p); });
+        }
+        assert_eq!(octree.len(), points.len());
+        assert_eq!(octree.nodes.len(), nodes);
+        assert_eq!(octree.points.len(), points.len());
+        assert_eq!(octree.nodes[octree.root.unwrap()].count, points.len());
+    }
+}
@@ -1,24 +1,82 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::fs::File;
 use std::io::{BufRead, BufReader, BufWriter, Write};
 use std::time::Instant;
 
-use linfa_linalg::eigh::{EighInto, EigSort};
 use ndarray::{Array, Array1, Array2, ArrayView, Axis, Ix1, Ix2, s};
-use ndarray_stats::CorrelationExt;
+use ordered_float::OrderedFloat;
+use rayon::prelude::*;
 
-use crate::nearest_neighbor::{knn_search, NNRes, NormalRes};
+use crate::cloud_view::CloudView;
+use crate::eigen3::{Covariance3, plane_covariance, symmetric_eigenvalues3, SymmetricEigen3};
+use crate::features::{Features, GeometricFeature};
+use crate::normal_estimator::{extent, inlier_ratio, NormalEstimator};
+use crate::region::Region;
+use crate::sampling::{farthest_point_indices, normal_space_indices, poisson_disk_indices, random_indices, SamplingStrategy,
+                      stability_indices, stratified_indices, stride_indices};
+use crate::voxel_grid::{centroid, voxel_members, VoxelDownsampling, VoxelRepresentative};
+use crate::nearest_neighbor::{knn_search, knn_search_in, radius_search, KdTreeIndex, NNRes, NormalRes, SpatialIndex};
+
+// At least 3 points are needed to define a plane
+const MIN_NORMAL_NEIGHBORS: usize = 3;
+// Variance along the normal of the regularized plane covariances
+const PLANE_COVARIANCE_EPSILON: f64 = 1e-3;
 
 pub struct CloudToCloudDist {
     pub nn: Vec<Vec<NNRes>>,
     pub dist: Array1<f64>,
 }
 
+/// Neighborhood used to estimate the normal vector of a point.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Neighborhood {
+    /// Fixed number of nearest neighbors.
+    Knn(usize),
+    /// All neighbors within a fixed radius. Points with less than `min_neighbors` are flagged.
+    Radius { radius: f64, min_neighbors: usize },
+    /// Per point the number of nearest neighbors in `k_min..=k_max` (increased by `step`) which
+    /// minimizes the eigenentropy of the local covariance matrix, see Weinmann et al. (2015).
+    AdaptiveKnn { k_min: usize, k_max: usize, step: usize },
+}
+
+/// Strategy to consistently orient the normal vectors, whose sign is arbitrary after estimation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NormalOrientation {
+    /// Orient all normals towards a single sensor origin.
+    Viewpoint([f64; 3]),
+    /// Orient each normal towards the scan position of its point (one row per point of the cloud).
+    ScanPositions(Array2<f64>),
+    /// Propagate the orientation along a minimum spanning tree over the k nearest neighbors, see
+    /// Hoppe et al. (1992). Suited for closed objects. The topmost point of each connected part is
+    /// oriented upwards.
+    MinimumSpanningTree { neighbors: usize },
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NormalStatus {
+    #[default]
+    NotEstimated,
+    Valid,
+    // Neighborhood was too small to estimate a normal - normal and planarity are NaN
+    TooFewNeighbors,
+}
+
 #[derive(Default)]
 pub struct PointCloud {
     points: Array2<f64>,
     planarity: Array1<f64>,
     normals: Array2<f64>,
-    selection: Option<Box<PointCloud>>,
+    inlier_ratio: Array1<f64>,
+    // Regularized covariances of the local planes (for GICP), given with the normals
+    covariances: Vec<[[f64; 3]; 3]>,
+    // RGB in [0, 1], NaN if the cloud has no colors
+    colors: Array2<f64>,
+    // Gradients of the intensity within the tangent planes (for colored ICP)
+    color_gradients: Array2<f64>,
+    normal_status: Vec<NormalStatus>,
+    features: Vec<Features>,
+    // Original indices of the selected points, see selection()
     selected_idx: Vec<usize>,
 }
 
@@ -33,19 +91,50 @@ impl PointCloud {
                 .expect("Could not create ndarray from points"),
             normals: Array::from_elem((point_amount, 3), f64::NAN),
             planarity: Array::from_elem(point_amount, f64::NAN),
-            selection: None,
+            inlier_ratio: Array::from_elem(point_amount, f64::NAN),
+            covariances: vec![[[f64::NAN; 3]; 3]; point_amount],
+            colors: Array::from_elem((point_amount, 3), f64::NAN),
+            color_gradients: Array::from_elem((point_amount, 3), f64::NAN),
+            normal_status: vec![NormalStatus::NotEstimated; point_amount],
+            features: Vec::new(),
             selected_idx: (0..point_amount).collect(),
         }
     }
 
-    pub fn select_from_cloud(cloud: &PointCloud, idx: &Vec<usize>) -> PointCloud {
-        let new_point_amount = idx.len() / 3;
+    // Point cloud with known normal vectors, e.g. from a previous estimation
+    pub fn with_normals(points: Vec<f64>, normals: Vec<f64>) -> PointCloud {
+        let mut cloud = PointCloud::new(points);
+        cloud.normals = Array::from_shape_vec((cloud.point_amount(), 3), normals)
+            .expect("Expected one normal per point");
+        cloud.normal_status = cloud.normals.outer_iter()
+            .map(|n| if n.iter().any(|v| v.is_nan()) { NormalStatus::NotEstimated } else { NormalStatus::Valid })
+            .collect();
+        cloud.covariances = cloud.normals.outer_iter()
+            .map(|n| plane_covariance(&[n[0], n[1], n[2]], PLANE_COVARIANCE_EPSILON))
+            .collect();
+        cloud
+    }
+
+    // Point cloud with RGB colors in [0, 1]
+    pub fn with_colors(points: Vec<f64>, colors: Vec<f64>) -> PointCloud {
+        let mut cloud = PointCloud::new(points);
+        cloud.colors = Array::from_shape_vec((cloud.point_amount(), 3), colors)
+            .expect("Expected one color per point");
+        cloud
+    }
+
+    pub fn select_from_cloud(cloud: &PointCloud, idx: &[usize]) -> PointCloud {
         PointCloud {
             points: cloud.points.select(Axis(0), idx),
             normals: cloud.normals.select(Axis(0), idx),
             planarity: cloud.planarity.select(Axis(0), idx),
-            selection: None,
-            selected_idx: (0..new_point_amount).collect(),
+            inlier_ratio: cloud.inlier_ratio.select(Axis(0), idx),
+            covariances: idx.iter().map(|i| cloud.covariances[*i]).collect(),
+            colors: cloud.colors.select(Axis(0), idx),
+            color_gradients: cloud.color_gradients.select(Axis(0), idx),
+            normal_status: idx.iter().map(|i| cloud.normal_status[*i]).collect(),
+            features: Self::select_features(&cloud.features, idx),
+            selected_idx: (0..idx.len()).collect(),
         }
     }
 
@@ -54,8 +143,7 @@ impl PointCloud {
         let reader = BufReader::new(file);
         let point_data = reader
             .lines()
-            .into_iter()
-            .map(|l| {
+            .flat_map(|l| {
                 let line = l.expect("Could not read line");
                 let xyz: Vec<f64> = line.split_whitespace().map(|part| {
                     let coord: f64 = part.parse().expect("Unable to parse coordinate");
@@ -63,39 +151,86 @@ impl PointCloud {
                 }).collect();
                 xyz
             })
-            .flatten()
             .collect();
         PointCloud::new(point_data)
     }
 
-    pub fn write_to_file(cloud: &PointCloud, name: &str) {
+    // Reads lines of x y z r g b with color channels in [0, max_color] (e.g. 255 for 8 bit colors, 1
+    // if they are already normalized), which are scaled to [0, 1]
+    pub fn read_from_xyzrgb(path: &str, max_color: f64) -> PointCloud {
+        assert!(max_color > 0.0, "max_color must be > 0");
+        let file = File::open(path).expect("Could not read pointcloud from file");
+        let reader = BufReader::new(file);
+        let mut points = Vec::new();
+        let mut colors = Vec::new();
+        for line in reader.lines() {
+            let line = line.expect("Could not read line");
+            let values: Vec<f64> = line.split_whitespace()
+                .map(|part| part.parse().expect("Unable to parse value"))
+                .collect();
+            if values.is_empty() {
+                continue;
+            }
+            assert_eq!(values.len(), 6, "Expected x y z r g b per line");
+            points.extend_from_slice(&values[..3]);
+            colors.extend_from_slice(&values[3..]);
+        }
+        if let Some(c) = colors.iter().find(|c| !(0.0..=max_color).contains(*c)) {
+            panic!("Color value {} is outside of [0, {}]", c, max_color);
+        }
+        colors.iter_mut().for_each(|c| *c /= max_color);
+        PointCloud::with_colors(points, colors)
+    }
+
+    pub fn write_to_file(cloud: &CloudView, name: &str) {
         let file = File::create(name).expect("Could not open file");
         let mut writer = BufWriter::new(file);
-        for pt in cloud.points().outer_iter() {
-            write!(writer, "{} ", pt[[0]]).expect("Unable to write to file");
-            write!(writer, "{} ", pt[[1]]).expect("Unable to write to file");
-            write!(writer, "{}\n", pt[[2]]).expect("Unable to write to file");
+        for pt in cloud.points() {
+            write!(writer, "{} ", pt[0]).expect("Unable to write to file");
+            write!(writer, "{} ", pt[1]).expect("Unable to write to file");
+            writeln!(writer, "{}", pt[2]).expect("Unable to write to file");
+        }
+    }
+
+    // Writes the points together with all geometric features (one column per feature and scale)
+    pub fn write_features_to_file(cloud: &PointCloud, name: &str) {
+        let file = File::create(name).expect("Could not open file");
+        let mut writer = BufWriter::new(file);
+
+        write!(writer, "//X Y Z").expect("Unable to write to file");
+        for scale in 0..cloud.features.len() {
+            for feature in GeometricFeature::ALL {
+                write!(writer, " {}_{}", feature.name(), scale).expect("Unable to write to file");
+            }
+        }
+        writeln!(writer).expect("Unable to write to file");
+
+        for (i, pt) in cloud.points().outer_iter().enumerate() {
+            write!(writer, "{} {} {}", pt[[0]], pt[[1]], pt[[2]]).expect("Unable to write to file");
+            for features in &cloud.features {
+                for value in features.values.row(i) {
+                    write!(writer, " {}", value).expect("Unable to write to file");
+                }
+            }
+            writeln!(writer).expect("Unable to write to file");
         }
     }
 
-    pub fn cloud_to_cloud_distance(pc1: &PointCloud, pc2: &PointCloud) -> CloudToCloudDist {
-        let nn_res = knn_search(pc2, pc1, 1);
+    // Point-to-plane distances of the points of pc1 to their nearest neighbor in pc2. The indices of
+    // the neighbors refer to the original indices of pc2.
+    pub fn cloud_to_cloud_distance(pc1: &CloudView, pc2: &CloudView) -> CloudToCloudDist {
+        Self::cloud_to_cloud_distance_in(pc1, &KdTreeIndex::from_view(pc2))
+    }
+
+    // Same as cloud_to_cloud_distance, but searches the correspondences in any spatial index,
+    // e.g. an incrementally growing octree map
+    pub fn cloud_to_cloud_distance_in<I: SpatialIndex>(pc1: &CloudView, index: &I) -> CloudToCloudDist {
+        let nn_res = knn_search_in(index, pc1, 1);
         let dists: Vec<f64> = pc1.points()
-            .outer_iter()
-            .zip(pc1.normals().outer_iter())
+            .zip(pc1.normals())
             .zip(nn_res.iter())
-            .map(|((p1, n1), nn)| {
-                let x1 = p1[[0]];
-                let y1 = p1[[1]];
-                let z1 = p1[[2]];
-
-                let x2 = pc2.points()[[nn[0].idx, 0]];
-                let y2 = pc2.points()[[nn[0].idx, 1]];
-                let z2 = pc2.points()[[nn[0].idx, 2]];
-
-                let nx1 = n1[[0]];
-                let ny1 = n1[[1]];
-                let nz1 = n1[[2]];
+            .map(|(([x1, y1, z1], [nx1, ny1, nz1]), nn)| {
+                let [x2, y2, z2] = index.point(nn[0].idx);
                 (x2 - x1) * nx1 + (y2 - y1) * ny1 + (z2 - z1) * nz1
             }).collect();
         CloudToCloudDist {
@@ -125,14 +260,45 @@ impl PointCloud {
         self.normals.view()
     }
 
-    pub fn selection(&self) -> &PointCloud {
-        match self.selection {
-            Some(ref x) => x,
-            None => self
-        }
+    pub fn inlier_ratio(&self) -> ArrayView<'_, f64, Ix1> {
+        self.inlier_ratio.view()
+    }
+
+    pub fn covariances(&self) -> &[[[f64; 3]; 3]] {
+        &self.covariances
+    }
+
+    pub fn colors(&self) -> ArrayView<'_, f64, Ix2> {
+        self.colors.view()
+    }
+
+    pub fn has_colors(&self) -> bool {
+        !self.colors.iter().any(|c| c.is_nan())
+    }
+
+    pub fn color_gradients(&self) -> ArrayView<'_, f64, Ix2> {
+        self.color_gradients.view()
+    }
+
+    pub fn normal_status(&self) -> &[NormalStatus] {
+        &self.normal_status
+    }
+
+    pub fn features(&self) -> &[Features] {
+        &self.features
     }
 
-    pub fn selection_idx(&self) -> &Vec<usize> {
+    // View on all points
+    pub fn view(&self) -> CloudView<'_> {
+        CloudView::new(self)
+    }
+
+    // View on the points selected so far, e.g. by select_in_range and select_n_pts
+    pub fn selection(&self) -> CloudView<'_> {
+        CloudView::from_indices(self, self.selected_idx.as_slice())
+    }
+
+    pub fn selection_idx(&self) -> &[usize] {
         &self.selected_idx
     }
 }
@@ -142,99 +308,551 @@ impl PointCloud {
 //###############################
 
 impl PointCloud {
-    pub fn select_in_range(&mut self, cloud: &mut PointCloud, max_range: f64) {
+    pub fn select_in_range(&mut self, cloud: &PointCloud, max_range: f64) {
         let now = Instant::now();
-        let query = self.selection();
-
         // Get nearest neighbours
-        let nn = knn_search(cloud, query, 1);
-
-        let mut nn_iter = nn.iter();
-        self.selected_idx.retain(|_| {
-            let nn_item = nn_iter.next().unwrap();
-            nn_item[0].distance <= max_range
-        });
+        let nn = knn_search(&cloud.view(), &self.selection(), 1);
 
-        self.selection = Option::from(Box::new(PointCloud::select_from_cloud(self, &self.selected_idx)));
+        // The distances of the neighbors are squared
+        self.retain_selected(|i| nn[i][0].distance <= max_range * max_range);
         println!("select_in_range took: {}", now.elapsed().as_millis());
     }
 
+    // Restricts the selections of both clouds to the points which are within max_range of the
+    // selection of the other cloud
+    pub fn select_in_mutual_range(&mut self, other: &mut PointCloud, max_range: f64) {
+        let now = Instant::now();
+        let nn_self = knn_search(&other.selection(), &self.selection(), 1);
+        let nn_other = knn_search(&self.selection(), &other.selection(), 1);
+
+        self.retain_selected(|i| nn_self[i][0].distance <= max_range * max_range);
+        other.retain_selected(|i| nn_other[i][0].distance <= max_range * max_range);
+        println!("select_in_mutual_range took: {}", now.elapsed().as_millis());
+    }
+
+    // Replaces the selection by the points with the given original indices
+    pub fn set_selection(&mut self, idx: &[usize]) {
+        assert!(idx.iter().all(|i| *i < self.point_amount()), "Index out of bounds of the point cloud");
+        self.selected_idx = idx.to_vec();
+    }
+
     pub fn select_n_pts(&mut self, n: usize) {
+        self.select_n_pts_with(n, &SamplingStrategy::Stride);
+    }
+
+    pub fn select_n_pts_with(&mut self, n: usize, strategy: &SamplingStrategy) {
         let now = Instant::now();
         // Todo: Build in check for "n"
         if n < self.selected_idx.len() {
-            let dist_idx = Array::<f64, _>::linspace(0., (self.selected_idx.len() - 1) as f64, n);
-            let mut dist_idx_iter = dist_idx.iter();
-            let mut actual_idx = dist_idx_iter.next().unwrap().floor() as usize;
-
-            let all_idx: Vec<usize> = (0..self.selected_idx.len()).collect();
-            let mut all_idx_iter = all_idx.iter();
-
-            self.selected_idx.retain(|_| {
-                let next_idx = all_idx_iter.next().unwrap();
-                if *next_idx == actual_idx {
-                    match dist_idx_iter.next() {
-                        None => {
-                            actual_idx = usize::MAX;
-                        }
-                        Some(new_idx) => {
-                            actual_idx = new_idx.floor() as usize;
-                        }
-                    }
-                    true
-                } else { false }
-            });
-
-            self.selection = Option::from(Box::new(PointCloud::select_from_cloud(self, &self.selected_idx)));
+            let keep = match *strategy {
+                SamplingStrategy::Stride => stride_indices(self.selected_idx.len(), n),
+                SamplingStrategy::NormalSpace { bins } => {
+                    normal_space_indices(&self.selection(), n, bins)
+                }
+                SamplingStrategy::Random { seed } => random_indices(self.selected_idx.len(), n, seed),
+                SamplingStrategy::Stratified { seed } => stratified_indices(&self.selection(), n, seed),
+                SamplingStrategy::Stability => {
+                    stability_indices(&self.selection(), n)
+                }
+                SamplingStrategy::FarthestPoint => farthest_point_indices(&self.selection(), n),
+            };
+            self.selected_idx = keep.iter().map(|i| self.selected_idx[*i]).collect();
         }
         println!("select_n_pts took: {}", now.elapsed().as_millis());
     }
 
-    pub fn estimate_normals(&mut self, neighbors: usize) {
+    pub fn select_farthest_points(&mut self, n: usize) {
+        self.select_n_pts_with(n, &SamplingStrategy::FarthestPoint);
+    }
+
+    // Thins the selected points, such that no two of them are closer than radius
+    pub fn select_poisson_disk(&mut self, radius: f64) {
+        let now = Instant::now();
+        let keep = poisson_disk_indices(&self.selection(), radius);
+        self.selected_idx = keep.iter().map(|i| self.selected_idx[*i]).collect();
+        println!("select_poisson_disk took: {}", now.elapsed().as_millis());
+    }
+
+    // Removes selected points whose mean distance to their k nearest (selected) neighbors is larger
+    // than mean + std_ratio * std of the mean distances of all selected points. Returns the original
+    // indices of the removed points.
+    pub fn remove_statistical_outliers(&mut self, k: usize, std_ratio: f64) -> Vec<usize> {
         let now = Instant::now();
-        let query_points = self.selection();
+        let selection = self.selection();
+        // The nearest neighbor of each point is the point itself
+        let mean_dists: Array1<f64> = knn_search(&selection, &selection, k + 1)
+            .iter()
+            .map(|nn| nn.iter().skip(1).map(|n| n.distance.sqrt()).sum::<f64>() / (nn.len() - 1).max(1) as f64)
+            .collect();
+        let max_dist = mean_dists.mean().unwrap_or(0.0) + std_ratio * mean_dists.std(0.0);
 
-        let nn = knn_search(self, query_points, neighbors);
+        let removed = self.retain_selected(|i| mean_dists[i] <= max_dist);
+        println!("remove_statistical_outliers took: {}", now.elapsed().as_millis());
+        removed
+    }
 
-        self.normals = Array::from_elem((self.point_amount(), 3), f64::NAN);
+    // Removes selected points with less than min_neighbors other selected points within radius.
+    // Returns the original indices of the removed points.
+    pub fn remove_radius_outliers(&mut self, radius: f64, min_neighbors: usize) -> Vec<usize> {
+        let now = Instant::now();
+        let selection = self.selection();
+        let neighbors: Vec<usize> = radius_search(&selection, &selection, radius)
+            .iter()
+            .map(|nn| nn.len().saturating_sub(1))
+            .collect();
 
-        for (i, idx) in self.selected_idx.iter().enumerate() {
-            let mut x_nn: Array<f64, Ix2> = Array::zeros((neighbors, 3));
+        let removed = self.retain_selected(|i| neighbors[i] >= min_neighbors);
+        println!("remove_radius_outliers took: {}", now.elapsed().as_millis());
+        removed
+    }
 
-            for j in 0..neighbors {
-                x_nn[[j, 0]] = self.points[[nn[i][j].idx, 0]];
-                x_nn[[j, 1]] = self.points[[nn[i][j].idx, 1]];
-                x_nn[[j, 2]] = self.points[[nn[i][j].idx, 2]];
+    // Removes selected points which are outside of all regions, i.e. keeps the union of the regions.
+    // Returns the original indices of the removed points.
+    pub fn crop(&mut self, regions: &[Region]) -> Vec<usize> {
+        let now = Instant::now();
+        let selection = self.selection();
+        let inside: Vec<bool> = (0..selection.len())
+            .into_par_iter()
+            .map(|i| {
+                let p = selection.point(i);
+                regions.iter().any(|region| region.contains(&p))
+            })
+            .collect();
+
+        let removed = self.retain_selected(|i| inside[i]);
+        println!("crop took: {}", now.elapsed().as_millis());
+        removed
+    }
+
+    // Keeps the selected points (by position in the selection) for which keep returns true and
+    // returns the original indices of the others
+    fn retain_selected(&mut self, keep: impl Fn(usize) -> bool) -> Vec<usize> {
+        let mut removed = Vec::new();
+        let mut i = 0;
+        self.selected_idx.retain(|idx| {
+            let kept = keep(i);
+            if !kept {
+                removed.push(*idx);
             }
+            i += 1;
+            kept
+        });
+        removed
+    }
 
-            let normal = Self::normal_from_neighbors(&mut x_nn);
-            self.normals[[*idx, 0]] = normal.eigenvector[[0]];
-            self.normals[[*idx, 1]] = normal.eigenvector[[1]];
-            self.normals[[*idx, 2]] = normal.eigenvector[[2]];
-            self.planarity[[*idx]] = normal.planarity;
-            // println!("nx1: {} | ny1: {} | nz1: {}", self.normals[[*idx, 0]], self.normals[[*idx, 1]], self.normals[[*idx, 2]]);
+    // Applies the homogeneous transformation matrix h to the points and normals
+    pub fn transform(&mut self, h: &Array2<f64>) {
+        let r = h.slice(s![..3, ..3]);
+        let t = h.slice(s![..3, 3]);
+        self.points = self.points.dot(&r.t()) + t;
+        self.normals = self.normals.dot(&r.t());
+        self.color_gradients = self.color_gradients.dot(&r.t());
+        for c in self.covariances.iter_mut() {
+            let rc = r.dot(&ArrayView::from_shape((3, 3), c.as_flattened()).unwrap());
+            let rcr = rc.dot(&r.t());
+            *c = [0, 1, 2].map(|i| [0, 1, 2].map(|j| rcr[[i, j]]));
+        }
+    }
+
+    // Returns one point per occupied voxel of the selected points
+    pub fn voxel_downsample(&self, voxel_size: f64, representative: VoxelRepresentative) -> VoxelDownsampling {
+        let now = Instant::now();
+        let members: Vec<Vec<usize>> = voxel_members(&self.selection(), voxel_size)
+            .into_iter()
+            .map(|voxel| voxel.into_iter().map(|i| self.selected_idx[i]).collect())
+            .collect();
 
+        let centroids: Vec<[f64; 3]> = members.iter().map(|voxel| centroid(self.points(), voxel)).collect();
+        let nearest_idx: Vec<usize> = members.iter().zip(centroids.iter()).map(|(voxel, c)| {
+            let sq_dist = |i: usize| (0..3).map(|axis| (self.points[[i, axis]] - c[axis]).powi(2)).sum::<f64>();
+            *voxel.iter().min_by(|a, b| sq_dist(**a).total_cmp(&sq_dist(**b))).unwrap()
+        }).collect();
+
+        let mut cloud = PointCloud::select_from_cloud(self, &nearest_idx);
+
+        if representative == VoxelRepresentative::Centroid {
+            for (v, voxel) in members.iter().enumerate() {
+                cloud.points.row_mut(v).assign(&ArrayView::from(&centroids[v]));
+                self.average_attributes(voxel, &mut cloud, v);
+            }
         }
+        println!("voxel_downsample took: {}", now.elapsed().as_millis());
+
+        VoxelDownsampling { cloud, members, nearest_idx }
+    }
 
-        // If selection exists: partially update it
-        if let Some(sel) = &mut self.selection {
-            sel.normals = self.normals.select(Axis(0), &self.selected_idx);
-            sel.planarity = self.planarity.select(Axis(0), &self.selected_idx);
+    // Averages normals and attributes of the points idx and stores them as point v of target. Color
+    // gradients are reset to NaN.
+    fn average_attributes(&self, idx: &[usize], target: &mut PointCloud, v: usize) {
+        let valid: Vec<usize> = idx.iter()
+            .copied()
+            .filter(|i| self.normal_status[*i] == NormalStatus::Valid)
+            .collect();
+        if let Some(first) = valid.first() {
+            // Normals are aligned to the first one before averaging, as their sign is arbitrary
+            let reference = self.normals.row(*first);
+            let mut normal = Array1::<f64>::zeros(3);
+            for i in &valid {
+                let n = self.normals.row(*i);
+                if n.dot(&reference) < 0.0 { normal -= &n } else { normal += &n }
+            }
+            let normal = normal.clone() / normal.dot(&normal).sqrt();
+            target.covariances[v] = plane_covariance(&[normal[0], normal[1], normal[2]], PLANE_COVARIANCE_EPSILON);
+            target.normals.row_mut(v).assign(&normal);
+            target.planarity[v] = valid.iter().map(|i| self.planarity[*i]).sum::<f64>() / valid.len() as f64;
+            target.inlier_ratio[v] = valid.iter().map(|i| self.inlier_ratio[*i]).sum::<f64>() / valid.len() as f64;
+            target.normal_status[v] = NormalStatus::Valid;
         }
+
+        let colored: Vec<usize> = idx.iter().copied().filter(|i| !self.colors[[*i, 0]].is_nan()).collect();
+        if !colored.is_empty() {
+            let color = self.colors.select(Axis(0), &colored).mean_axis(Axis(0)).unwrap();
+            target.colors.row_mut(v).assign(&color);
+        }
+        // Gradients of the averaged colors are unknown, estimate_color_gradients has to be run again
+        target.color_gradients.row_mut(v).fill(f64::NAN);
+
+        for (source, target) in self.features.iter().zip(target.features.iter_mut()) {
+            for j in 0..source.values.ncols() {
+                let values: Vec<f64> = idx.iter().map(|i| source.values[[*i, j]]).filter(|f| !f.is_nan()).collect();
+                if !values.is_empty() {
+                    target.values[[v, j]] = values.iter().sum::<f64>() / values.len() as f64;
+                }
+            }
+        }
+    }
+
+    pub fn estimate_normals(&mut self, neighbors: usize) {
+        self.estimate_normals_with(&Neighborhood::Knn(neighbors));
+    }
+
+    pub fn estimate_normals_with(&mut self, neighborhood: &Neighborhood) {
+        self.estimate_normals_robust(neighborhood, &NormalEstimator::Pca);
+    }
+
+    pub fn estimate_normals_robust(&mut self, neighborhood: &Neighborhood, estimator: &NormalEstimator) {
+        let now = Instant::now();
+        self.normals = Array::from_elem((self.point_amount(), 3), f64::NAN);
+        self.planarity = Array::from_elem(self.point_amount(), f64::NAN);
+        self.inlier_ratio = Array::from_elem(self.point_amount(), f64::NAN);
+        self.covariances = vec![[[f64::NAN; 3]; 3]; self.point_amount()];
+        self.normal_status = vec![NormalStatus::NotEstimated; self.point_amount()];
+
+        self.fit_normals(&self.selected_idx.clone(), neighborhood, estimator);
         println!("estimate_normals took: {}", now.elapsed().as_millis());
     }
 
-    fn normal_from_neighbors(neighbors: &mut Array<f64, Ix2>) -> NormalRes {
-        let covariance = neighbors.t().cov(1.).unwrap();
-        let eig_res = covariance.eigh_into().unwrap();
-        let mut eig_sort = eig_res.sort_eig_desc();
-        let eig_vals = eig_sort.0.view();
+    // Estimates the normals of those points (given by original index) which have none yet, e.g. of
+    // the matched points of the moved cloud. Existing normals are kept.
+    pub fn estimate_missing_normals(&mut self, idx: &[usize], neighbors: usize) {
+        let mut missing: Vec<usize> = idx.iter()
+            .copied()
+            .filter(|i| self.normal_status[*i] == NormalStatus::NotEstimated)
+            .collect();
+        missing.sort_unstable();
+        missing.dedup();
+        if !missing.is_empty() {
+            self.fit_normals(&missing, &Neighborhood::Knn(neighbors), &NormalEstimator::Pca);
+        }
+    }
+
+    // Estimates the gradients of the intensity (mean of RGB) of the selected points within their tangent
+    // planes from the neighbors, i.e. the intensity at u close to point p is I_p + d_p * (u - p).
+    // Needs colors and normals.
+    pub fn estimate_color_gradients(&mut self, neighbors: usize) {
+        let now = Instant::now();
+        assert!(self.has_colors(), "Color gradients need colors");
+        let selection = self.selection();
+        let nn = knn_search(&self.view(), &selection, neighbors + 1);
+        let intensity = |i: usize| self.colors.row(i).mean().unwrap();
 
-        NormalRes {
-            eigenvector: eig_sort.1.slice_mut(s![.., 2]).to_owned(),
-            planarity: (eig_vals[1] - eig_vals[2]) / eig_vals[0],
+        let gradients: Vec<[f64; 3]> = selection.indices()
+            .par_iter()
+            .zip(nn.par_iter())
+            .map(|(i, nn)| {
+                let p = self.point(*i);
+                let n = [self.normals[[*i, 0]], self.normals[[*i, 1]], self.normals[[*i, 2]]];
+                // Normal equations of the neighbors projected onto the tangent plane, plus the
+                // constraint d * n = 0 weighted with the number of neighbors
+                let w = nn.len() as f64;
+                let mut ata = [0, 1, 2].map(|r| [0, 1, 2].map(|c| w * w * n[r] * n[c]));
+                let mut atb = [0.0; 3];
+                for q in nn.iter().filter(|q| q.idx != *i) {
+                    let q_point = self.point(q.idx);
+                    let diff = [0, 1, 2].map(|k| q_point[k] - p[k]);
+                    let height = (0..3).map(|k| diff[k] * n[k]).sum::<f64>();
+                    let a = [0, 1, 2].map(|k| diff[k] - height * n[k]);
+                    let b = intensity(q.idx) - intensity(*i);
+                    for r in 0..3 {
+                        (0..3).for_each(|c| ata[r][c] += a[r] * a[c]);
+                        atb[r] += a[r] * b;
+                    }
+                }
+                // Pseudo inverse, as degenerated neighborhoods (e.g. collinear) can't constrain all directions
+                let eigen = SymmetricEigen3::new(&ata);
+                let mut d = [0.0; 3];
+                for (value, vector) in eigen.values.iter().zip(eigen.vectors.iter()) {
+                    if *value > 1e-12 * eigen.values[0] {
+                        let projection = (0..3).map(|k| vector[k] * atb[k]).sum::<f64>() / value;
+                        (0..3).for_each(|k| d[k] += projection * vector[k]);
+                    }
+                }
+                d
+            })
+            .collect();
+
+        self.color_gradients = Array::from_elem((self.point_amount(), 3), f64::NAN);
+        for (i, d) in self.selected_idx.iter().zip(gradients) {
+            self.color_gradients.row_mut(*i).assign(&ArrayView::from(&d));
+        }
+        println!("estimate_color_gradients took: {}", now.elapsed().as_millis());
+    }
+
+    fn fit_normals(&mut self, idx: &[usize], neighborhood: &Neighborhood, estimator: &NormalEstimator) {
+        let (nn, min_neighbors) = self.search_neighborhoods(&CloudView::from_indices(self, idx), neighborhood);
+
+        let results: Vec<Option<NormalRes>> = idx
+            .par_iter()
+            .zip(nn.par_iter())
+            .map(|(idx, nn)| {
+                let nn = self.select_neighborhood(nn, neighborhood, min_neighbors)?;
+                Some(match estimator {
+                    NormalEstimator::Pca => self.local_plane(nn),
+                    _ => {
+                        let sq_dists: Vec<f64> = nn.iter().map(|n| n.distance).collect();
+                        estimator.fit(&self.neighbor_points(nn), &sq_dists, *idx)
+                    }
+                })
+            })
+            .collect();
+
+        for (idx, normal) in idx.iter().zip(results) {
+            let normal = match normal {
+                Some(normal) => normal,
+                None => {
+                    self.normal_status[*idx] = NormalStatus::TooFewNeighbors;
+                    continue;
+                }
+            };
+
+            self.normals.row_mut(*idx).assign(&ArrayView::from(&normal.eigenvector));
+            self.planarity[[*idx]] = normal.planarity;
+            self.inlier_ratio[[*idx]] = normal.inlier_ratio;
+            self.covariances[*idx] = plane_covariance(&normal.eigenvector, PLANE_COVARIANCE_EPSILON);
+            self.normal_status[*idx] = NormalStatus::Valid;
         }
     }
+
+    // Estimates all geometric features of the selected points at each of the given scales
+    pub fn estimate_features(&mut self, scales: &[Neighborhood]) {
+        let now = Instant::now();
+
+        self.features = scales.iter().map(|neighborhood| {
+            let (nn, min_neighbors) = self.search_neighborhoods(&self.selection(), neighborhood);
+            let mut values = Array::from_elem((self.point_amount(), GeometricFeature::ALL.len()), f64::NAN);
+
+            let eigenvalues: Vec<Option<[f64; 3]>> = self.selected_idx
+                .par_iter()
+                .zip(nn.par_iter())
+                .map(|(_, nn)| {
+                    let nn = self.select_neighborhood(nn, neighborhood, min_neighbors)?;
+                    Some(symmetric_eigenvalues3(&self.local_covariance(nn).covariance(1.0)))
+                })
+                .collect();
+
+            for (idx, eigenvalues) in self.selected_idx.iter().zip(eigenvalues) {
+                if let Some(eigenvalues) = eigenvalues {
+                    for (j, feature) in GeometricFeature::ALL.iter().enumerate() {
+                        values[[*idx, j]] = feature.from_eigenvalues(&eigenvalues);
+                    }
+                }
+            }
+            Features { neighborhood: neighborhood.clone(), values }
+        }).collect();
+
+        println!("estimate_features took: {}", now.elapsed().as_millis());
+    }
+
+    fn select_features(features: &[Features], idx: &[usize]) -> Vec<Features> {
+        features.iter().map(|f| Features {
+            neighborhood: f.neighborhood.clone(),
+            values: f.values.select(Axis(0), idx),
+        }).collect()
+    }
+
+    // Returns the neighbors (among all points) of the query points and the minimal size of a valid
+    // neighborhood
+    fn search_neighborhoods(&self, query_points: &CloudView, neighborhood: &Neighborhood) -> (Vec<Vec<NNRes>>, usize) {
+        let reference = self.view();
+        match *neighborhood {
+            Neighborhood::Knn(k) => (knn_search(&reference, query_points, k), MIN_NORMAL_NEIGHBORS),
+            Neighborhood::Radius { radius, min_neighbors } => {
+                (radius_search(&reference, query_points, radius), min_neighbors.max(MIN_NORMAL_NEIGHBORS))
+            }
+            Neighborhood::AdaptiveKnn { k_min, k_max, .. } => {
+                assert!(k_min <= k_max, "k_min must be <= k_max");
+                (knn_search(&reference, query_points, k_max), k_min.max(MIN_NORMAL_NEIGHBORS))
+            }
+        }
+    }
+
+    // Returns the neighbors which are actually used for a point, None if the neighborhood is too small
+    fn select_neighborhood<'n>(&self, nn: &'n [NNRes], neighborhood: &Neighborhood, min_neighbors: usize) -> Option<&'n [NNRes]> {
+        if nn.len() < min_neighbors {
+            return None;
+        }
+        match *neighborhood {
+            Neighborhood::AdaptiveKnn { step, .. } => {
+                // Grow the covariance neighbor by neighbor, so that each candidate size costs O(1)
+                let mut covariance = Covariance3::new(self.point(nn[0].idx));
+                let mut best = (f64::INFINITY, min_neighbors);
+                for (k, n) in nn.iter().enumerate().map(|(k, n)| (k + 1, n)) {
+                    covariance.add(&self.point(n.idx));
+                    if k >= min_neighbors && (k - min_neighbors).is_multiple_of(step.max(1)) {
+                        let eigenvalues = symmetric_eigenvalues3(&covariance.covariance(1.0));
+                        let entropy = GeometricFeature::Eigenentropy.from_eigenvalues(&eigenvalues);
+                        if entropy < best.0 {
+                            best = (entropy, k);
+                        }
+                    }
+                }
+                Some(&nn[..best.1])
+            }
+            _ => Some(nn)
+        }
+    }
+
+    pub fn orient_normals(&mut self, orientation: &NormalOrientation) {
+        let now = Instant::now();
+        let idx: Vec<usize> = self.selected_idx
+            .iter()
+            .copied()
+            .filter(|i| self.normal_status[*i] == NormalStatus::Valid)
+            .collect();
+
+        match orientation {
+            NormalOrientation::Viewpoint(origin) => {
+                for i in idx {
+                    self.orient_towards(i, origin);
+                }
+            }
+            NormalOrientation::ScanPositions(positions) => {
+                assert_eq!(positions.shape(), self.points.shape(), "Expected one scan position per point");
+                for i in idx {
+                    let origin = [positions[[i, 0]], positions[[i, 1]], positions[[i, 2]]];
+                    self.orient_towards(i, &origin);
+                }
+            }
+            NormalOrientation::MinimumSpanningTree { neighbors } => {
+                self.orient_along_spanning_tree(&idx, *neighbors);
+            }
+        }
+
+        println!("orient_normals took: {}", now.elapsed().as_millis());
+    }
+
+    fn orient_towards(&mut self, i: usize, origin: &[f64; 3]) {
+        let dot: f64 = (0..3).map(|axis| (origin[axis] - self.points[[i, axis]]) * self.normals[[i, axis]]).sum();
+        if dot < 0.0 {
+            self.normals.row_mut(i).mapv_inplace(|n| -n);
+        }
+    }
+
+    // Prim's algorithm on the symmetric knn graph with edge weights 1 - |n_i * n_j|
+    fn orient_along_spanning_tree(&mut self, idx: &[usize], neighbors: usize) {
+        let sub = CloudView::from_indices(self, idx);
+        let nn = knn_search(&sub, &sub, neighbors + 1);
+
+        // Neighbors are found by their original index, the graph is built over the positions in idx
+        let mut position = vec![usize::MAX; self.point_amount()];
+        idx.iter().enumerate().for_each(|(i, j)| position[*j] = i);
+
+        let mut graph: Vec<Vec<usize>> = vec![Vec::new(); idx.len()];
+        for (i, nn_i) in nn.iter().enumerate() {
+            for j in nn_i.iter().map(|n| position[n.idx]).filter(|j| *j != i) {
+                graph[i].push(j);
+                graph[j].push(i);
+            }
+        }
+
+        let normal = |cloud: &PointCloud, i: usize| cloud.normals.row(idx[i]).to_owned();
+        let mut visited = vec![false; idx.len()];
+
+        // Seeds are processed from top to bottom, so that each connected part starts at its topmost point
+        let mut seeds: Vec<usize> = (0..idx.len()).collect();
+        seeds.sort_by(|a, b| sub.point(*b)[2].total_cmp(&sub.point(*a)[2]));
+
+        for seed in seeds {
+            if visited[seed] {
+                continue;
+            }
+            if self.normals[[idx[seed], 2]] < 0.0 {
+                self.normals.row_mut(idx[seed]).mapv_inplace(|n| -n);
+            }
+
+            let mut queue = BinaryHeap::new();
+            queue.push(Reverse((OrderedFloat(0.0), seed, seed)));
+            while let Some(Reverse((_, parent, i))) = queue.pop() {
+                if visited[i] {
+                    continue;
+                }
+                visited[i] = true;
+
+                let n_i = normal(self, i);
+                if n_i.dot(&normal(self, parent)) < 0.0 {
+                    self.normals.row_mut(idx[i]).mapv_inplace(|n| -n);
+                }
+
+                let n_i = normal(self, i);
+                for j in graph[i].iter().copied().filter(|j| !visited[*j]) {
+                    let weight = 1.0 - n_i.dot(&normal(self, j)).abs();
+                    queue.push(Reverse((OrderedFloat(weight), i, j)));
+                }
+            }
+        }
+    }
+
+    fn point(&self, i: usize) -> [f64; 3] {
+        [self.points[[i, 0]], self.points[[i, 1]], self.points[[i, 2]]]
+    }
+
+    fn neighbor_points(&self, nn: &[NNRes]) -> Array<f64, Ix2> {
+        let idx: Vec<usize> = nn.iter().map(|n| n.idx).collect();
+        self.points.select(Axis(0), &idx)
+    }
+
+    fn local_covariance(&self, nn: &[NNRes]) -> Covariance3 {
+        let mut covariance = Covariance3::new(self.point(nn[0].idx));
+        for n in nn {
+            covariance.add(&self.point(n.idx));
+        }
+        covariance
+    }
+
+    // Same plane and MAD-based inlier ratio as NormalEstimator::Pca, without copying the neighbors
+    fn local_plane(&self, nn: &[NNRes]) -> NormalRes {
+        let covariance = self.local_covariance(nn);
+        let mut normal = NormalRes::from_covariance(&covariance.covariance(1.0));
+
+        let (n, mean) = (normal.eigenvector, covariance.mean());
+        let residuals: Vec<f64> = nn.iter()
+            .map(|neighbor| {
+                let p = self.point(neighbor.idx);
+                (0..3).map(|axis| (p[axis] - mean[axis]) * n[axis]).sum()
+            })
+            .collect();
+        let extent = extent(nn.iter().flat_map(|neighbor| self.point(neighbor.idx)));
+        normal.inlier_ratio = inlier_ratio(&residuals, extent, None);
+        normal
+    }
+
+    pub fn normal_from_neighbors(neighbors: &Array<f64, Ix2>) -> NormalRes {
+        let mut covariance = Covariance3::new([neighbors[[0, 0]], neighbors[[0, 1]], neighbors[[0, 2]]]);
+        for p in neighbors.outer_iter() {
+            covariance.add(&[p[0], p[1], p[2]]);
+        }
+        NormalRes::from_covariance(&covariance.covariance(1.0))
+    }
 }
 
 
@@ -243,7 +861,11 @@ mod point_cloud_test {
     use linfa_linalg::norm::Norm;
     use ndarray::{array, Array, Ix2};
 
-    use crate::pointcloud::{NormalRes, PointCloud};
+    use crate::features::GeometricFeature;
+    use crate::normal_estimator::NormalEstimator;
+    use crate::octree::Octree;
+    use crate::pointcloud::{Neighborhood, NormalOrientation, NormalStatus, PointCloud};
+    use crate::region::Region;
 
     fn get_points() -> Array<f64, Ix2> {
         array![
@@ -261,8 +883,8 @@ mod point_cloud_test {
 
     #[test]
     fn normals_from_neighbors() {
-        let mut points = get_points();
-        let normal = PointCloud::normal_from_neighbors(&mut points);
+        let points = get_points();
+        let normal = PointCloud::normal_from_neighbors(&points);
         let expected = array![1., 0., -1.];
         let norm = expected.norm_l2();
 
@@ -274,4 +896,149 @@ mod point_cloud_test {
         assert_float_absolute_eq!(normalized[[1]], 0.0, delta);
         assert_float_absolute_eq!(normalized[[2]], -0.7, delta);
     }
+
+    #[test]
+    fn estimate_normals_with_sparse_neighborhoods() {
+        let mut points: Vec<f64> = get_points().into_iter().collect();
+        points.extend_from_slice(&[10., 10., 10.]);
+
+        let mut cloud = PointCloud::new(points.clone());
+        cloud.estimate_normals_with(&Neighborhood::Radius { radius: 1.5, min_neighbors: 4 });
+        assert_eq!(cloud.normal_status()[4], NormalStatus::Valid);
+        assert_eq!(cloud.normal_status()[9], NormalStatus::TooFewNeighbors);
+        assert!(cloud.normals()[[9, 0]].is_nan());
+        assert_float_absolute_eq!(cloud.normals()[[4, 1]], 0.0, 1e-6);
+
+        let mut cloud = PointCloud::new(points);
+        cloud.estimate_normals_with(&Neighborhood::AdaptiveKnn { k_min: 4, k_max: 9, step: 1 });
+        assert_eq!(cloud.normal_status()[4], NormalStatus::Valid);
+        assert_float_absolute_eq!(cloud.normals()[[4, 0]].abs(), 0.707, 0.01);
+        assert_float_absolute_eq!(cloud.normals()[[4, 2]].abs(), 0.707, 0.01);
+    }
+
+    #[test]
+    fn fast_pca_path_reports_inlier_ratio() {
+        // Plane with three points of a perpendicular wall, all points are neighbors of each other
+        let mut points: Vec<f64> = (0..25).flat_map(|i| [(i % 5) as f64 * 0.1, (i / 5) as f64 * 0.1, 0.0]).collect();
+        points.extend_from_slice(&[0.2, 0.0, 0.3, 0.2, 0.1, 0.4, 0.2, 0.2, 0.5]);
+        let mut cloud = PointCloud::new(points);
+        cloud.estimate_normals(28);
+
+        let expected = NormalEstimator::Pca.fit(&cloud.points().to_owned(), &[0.0; 28], 0);
+        assert!(expected.inlier_ratio < 1.0);
+        for ratio in cloud.inlier_ratio() {
+            assert_float_absolute_eq!(*ratio, expected.inlier_ratio, 1e-9);
+        }
+    }
+
+    #[test]
+    fn orient_normals_on_sphere() {
+        // Fibonacci lattice on the unit sphere
+        let n = 500;
+        let golden_angle = std::f64::consts::PI * (3.0 - 5.0_f64.sqrt());
+        let points: Vec<f64> = (0..n).flat_map(|i| {
+            let z = 1.0 - 2.0 * (i as f64 + 0.5) / n as f64;
+            let r = (1.0 - z * z).sqrt();
+            let phi = golden_angle * i as f64;
+            [r * phi.cos(), r * phi.sin(), z]
+        }).collect();
+
+        let orientation = |cloud: &PointCloud| -> Vec<bool> {
+            cloud.points().outer_iter().zip(cloud.normals().outer_iter()).map(|(p, n)| p.dot(&n) > 0.0).collect()
+        };
+
+        let mut cloud = PointCloud::new(points);
+        cloud.estimate_normals(10);
+        cloud.orient_normals(&NormalOrientation::MinimumSpanningTree { neighbors: 8 });
+        assert!(orientation(&cloud).iter().all(|outwards| *outwards));
+
+        cloud.orient_normals(&NormalOrientation::Viewpoint([0., 0., 0.]));
+        assert!(orientation(&cloud).iter().all(|outwards| !*outwards));
+    }
+
+    #[test]
+    fn estimate_features_at_several_scales() {
+        let mut cloud = PointCloud::new(get_points().into_iter().collect());
+        cloud.estimate_features(&[Neighborhood::Knn(4), Neighborhood::Knn(9)]);
+
+        assert_eq!(cloud.features().len(), 2);
+        let planarity = cloud.features()[1].get(GeometricFeature::Planarity);
+        assert_float_absolute_eq!(planarity[4], 0.5, 0.01);
+        assert_float_absolute_eq!(cloud.features()[1].get(GeometricFeature::Sphericity)[4], 0.0, 1e-9);
+    }
+
+    #[test]
+    fn remove_isolated_points() {
+        // Regular grid with two isolated points (index 100 and 102)
+        let mut points: Vec<f64> = (0..100).flat_map(|i| [(i % 10) as f64, (i / 10) as f64, 0.0]).collect();
+        points.extend_from_slice(&[4.5, 4.5, 20.0, 5.0, 5.0, 0.1, -30.0, 0.0, 0.0]);
+
+        let mut cloud = PointCloud::new(points.clone());
+        assert_eq!(cloud.remove_statistical_outliers(4, 2.0), vec![100, 102]);
+        assert_eq!(cloud.selection_idx().len(), 101);
+
+        let mut cloud = PointCloud::new(points);
+        cloud.select_n_pts(102);
+        assert_eq!(cloud.remove_radius_outliers(1.5, 2), vec![100, 102]);
+        assert!(!cloud.selection_idx().contains(&102));
+    }
+
+    #[test]
+    fn select_in_mutual_range() {
+        let mut fixed = PointCloud::new(vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 2.0, 0.0, 0.0]);
+        let mut moved = PointCloud::new(vec![1.1, 0.0, 0.0, 2.1, 0.0, 0.0, 3.1, 0.0, 0.0]);
+
+        fixed.select_in_mutual_range(&mut moved, 0.5);
+        assert_eq!(fixed.selection_idx(), &[1, 2]);
+        assert_eq!(moved.selection_idx(), &[0, 1]);
+    }
+
+    #[test]
+    fn crop_to_region() {
+        let mut cloud = PointCloud::new(get_points().into_iter().collect());
+        let removed = cloud.crop(&[Region::Sphere { center: [2.0, 2.0, 2.0], radius: 1.5 }]);
+        assert_eq!(removed, vec![0, 2, 6, 8]);
+
+        cloud.crop(&[Region::aligned_box([0.0; 3], [2.5, 2.5, 2.5]).inverted()]);
+        assert_eq!(cloud.selection_idx(), &[5, 7]);
+
+        // Points inside any of the regions are kept
+        let mut cloud = PointCloud::new(get_points().into_iter().collect());
+        cloud.crop(&[
+            Region::Sphere { center: [1.0, 1.0, 1.0], radius: 0.1 },
+            Region::aligned_box([2.5, 2.5, 2.5], [3.5, 3.5, 3.5]),
+        ]);
+        assert_eq!(cloud.selection_idx(), &[0, 8]);
+    }
+    #[test]
+    fn cloud_to_cloud_distance_in_octree() {
+        let wave = |x: f64, y: f64| 0.1 * (2.0 * x).sin() * (3.0 * y).cos();
+        let mut fixed = Vec::new();
+        let mut moved = Vec::new();
+        for i in 0..20 {
+            for j in 0..20 {
+                let (x, y) = (i as f64 * 0.1, j as f64 * 0.1);
+                fixed.extend_from_slice(&[x, y, wave(x, y)]);
+                let (x, y) = (x + 0.013, y + 0.027);
+                moved.extend_from_slice(&[x, y, wave(x, y) + 0.05]);
+            }
+        }
+        let mut fixed = PointCloud::new(fixed);
+        fixed.estimate_normals(8);
+        let moved = PointCloud::new(moved);
+
+        let expected = PointCloud::cloud_to_cloud_distance(&fixed.view(), &moved.view());
+        // Ids of the octree equal the indices of the cloud, as no point is dropped
+        let octree = Octree::from_cloud(&moved, 8, 0.01);
+        assert_eq!(octree.len(), moved.point_amount());
+        let result = PointCloud::cloud_to_cloud_distance_in(&fixed.view(), &octree);
+
+        for (res, exp) in result.nn.iter().zip(expected.nn.iter()) {
+            assert_eq!(res[0].idx, exp[0].idx);
+            assert_float_absolute_eq!(res[0].distance, exp[0].distance, 1e-12);
+        }
+        for (res, exp) in result.dist.iter().zip(expected.dist.iter()) {
+            assert_float_absolute_eq!(*res, *exp, 1e-12);
+        }
+    }
 }
@@ -0,0 +1,280 @@
+use std::fs;
+
+use serde_json::Value;
+
+use crate::rigid_body_transformation::euler_angles_to_rotation_matrix;
+
+/// Region of space, e.g. to crop a point cloud to a stable part of the scene.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Region {
+    /// Box with the given center and half edge lengths, whose axes are the columns of `rotation`.
+    OrientedBox { center: [f64; 3], half_size: [f64; 3], rotation: [[f64; 3]; 3] },
+    Sphere { center: [f64; 3], radius: f64 },
+    /// 2D polygon in the xy plane (closing vertex optional), extruded from `z_min` to `z_max`.
+    Polygon { vertices: Vec<[f64; 2]>, z_min: f64, z_max: f64 },
+    /// Everything outside of the inner region.
+    Inverted(Box<Region>),
+}
+
+impl Region {
+    // Axis-aligned box between the corners min and max
+    pub fn aligned_box(min: [f64; 3], max: [f64; 3]) -> Region {
+        Region::OrientedBox {
+            center: [0, 1, 2].map(|axis| (min[axis] + max[axis]) / 2.0),
+            half_size: [0, 1, 2].map(|axis| (max[axis] - min[axis]).abs() / 2.0),
+            rotation: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        }
+    }
+
+    pub fn inverted(self) -> Region {
+        match self {
+            Region::Inverted(inner) => *inner,
+            region => Region::Inverted(Box::new(region)),
+        }
+    }
+
+    pub fn contains(&self, p: &[f64; 3]) -> bool {
+        match self {
+            Region::OrientedBox { center, half_size, rotation } => {
+                let d = [0, 1, 2].map(|axis| p[axis] - center[axis]);
+                (0..3).all(|col| {
+                    let local: f64 = (0..3).map(|row| rotation[row][col] * d[row]).sum();
+                    local.abs() <= half_size[col]
+                })
+            }
+            Region::Sphere { center, radius } => {
+                (0..3).map(|axis| (p[axis] - center[axis]).powi(2)).sum::<f64>() <= radius * radius
+            }
+            Region::Polygon { vertices, z_min, z_max } => {
+                *z_min <= p[2] && p[2] <= *z_max && polygon_contains(vertices, p[0], p[1])
+            }
+            Region::Inverted(inner) => !inner.contains(p),
+        }
+    }
+}
+
+// Even-odd rule: a ray from (x, y) in +x direction crosses the boundary an odd number of times
+fn polygon_contains(vertices: &[[f64; 2]], x: f64, y: f64) -> bool {
+    let mut inside = false;
+    for (i, a) in vertices.iter().enumerate() {
+        let b = vertices[(i + 1) % vertices.len()];
+        if (a[1] > y) != (b[1] > y) && x < a[0] + (y - a[1]) / (b[1] - a[1]) * (b[0] - a[0]) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+//###############################
+//#     Reading of regions      #
+//###############################
+
+// Reads the regions of a GeoJSON file (if it starts with '{') or of a WKT file (one geometry per line)
+pub fn read_regions(path: &str) -> Result<Vec<Region>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Could not read regions from '{}': {}", path, e))?;
+    if text.trim_start().starts_with('{') {
+        regions_from_geojson(&text)
+    } else {
+        text.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(region_from_wkt)
+            .collect()
+    }
+}
+
+// Parses a WKT "POLYGON ((x y, ...))" (unbounded in z) or "POLYGON Z ((x y z, ...))" (bounded by the
+// z values of the vertices). Keywords are case-insensitive and may be written as "POLYGONZ". Only the
+// outer ring is used.
+pub fn region_from_wkt(wkt: &str) -> Result<Region, String> {
+    let wkt = wkt.trim();
+    let open = wkt.find('(').unwrap_or(wkt.len());
+    let header: String = wkt[..open].split_whitespace().collect::<String>().to_uppercase();
+    let has_z = match header.as_str() {
+        "POLYGON" => false,
+        "POLYGONZ" => true,
+        _ => return Err(format!(
+            "Unsupported WKT geometry '{}', only POLYGON and POLYGON Z are supported",
+            wkt[..open].trim()
+        )),
+    };
+
+    let ring = wkt.get(open + 1..)
+        .and_then(|rest| rest.trim_start().strip_prefix('('))
+        .ok_or("Expected '((' in WKT polygon")?;
+    let end = ring.find(')').ok_or("Expected ')' in WKT polygon")?;
+    let dim = if has_z { 3 } else { 2 };
+    let coords = ring[..end]
+        .split(',')
+        .map(|vertex| {
+            let c = vertex.split_whitespace()
+                .map(|part| part.parse().map_err(|_| format!("Unable to parse coordinate '{}'", part)))
+                .collect::<Result<Vec<f64>, String>>()?;
+            if c.len() != dim {
+                return Err(format!("Expected {} coordinates per vertex of WKT polygon, got '{}'", dim, vertex.trim()));
+            }
+            Ok(c)
+        })
+        .collect::<Result<Vec<Vec<f64>>, String>>()?;
+
+    let (z_min, z_max) = if has_z {
+        coords.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), c| (lo.min(c[2]), hi.max(c[2])))
+    } else {
+        (f64::NEG_INFINITY, f64::INFINITY)
+    };
+    Ok(Region::Polygon { vertices: coords.iter().map(|c| [c[0], c[1]]).collect(), z_min, z_max })
+}
+
+// Parses a FeatureCollection, a single Feature or a bare geometry:
+// - Polygon: polygon region, bounded in z by the properties "z_min" and "z_max" (if given)
+// - Point with the property "radius": sphere around the (3D) point
+// - properties "center" and "half_size" (and optionally "rotation" as Euler angles alpha1, alpha2,
+//   alpha3 in radian) without geometry: oriented box
+// The property "inverted": true inverts a region.
+pub fn regions_from_geojson(text: &str) -> Result<Vec<Region>, String> {
+    let json: Value = serde_json::from_str(text).map_err(|e| format!("Invalid GeoJSON: {}", e))?;
+    match json["type"].as_str() {
+        Some("FeatureCollection") => json["features"]
+            .as_array()
+            .ok_or("Expected a 'features' array")?
+            .iter()
+            .map(region_from_feature)
+            .collect(),
+        _ => Ok(vec![region_from_feature(&json)?]),
+    }
+}
+
+fn region_from_feature(feature: &Value) -> Result<Region, String> {
+    let (geometry, properties) = match feature["type"].as_str() {
+        Some("Feature") => (&feature["geometry"], &feature["properties"]),
+        _ => (feature, &Value::Null),
+    };
+    let number = |name: &str| match &properties[name] {
+        Value::Null => Ok(None),
+        value => value.as_f64().map(Some).ok_or(format!("Expected a number as property '{}'", name)),
+    };
+    let vector = |name: &str| match &properties[name] {
+        Value::Null => Ok(None),
+        value => to_vec3(value).map(Some),
+    };
+
+    let region = if geometry.is_null() {
+        let angles = vector("rotation")?.unwrap_or([0.0; 3]);
+        let r = euler_angles_to_rotation_matrix(angles[0], angles[1], angles[2]);
+        Region::OrientedBox {
+            center: vector("center")?.ok_or("Expected the property 'center' for a box")?,
+            half_size: vector("half_size")?.ok_or("Expected the property 'half_size' for a box")?,
+            rotation: [0, 1, 2].map(|row| [0, 1, 2].map(|col| r[[row, col]])),
+        }
+    } else {
+        let coordinates = &geometry["coordinates"];
+        match geometry["type"].as_str() {
+            Some("Polygon") => {
+                let vertices = coordinates[0]
+                    .as_array()
+                    .ok_or("Expected an array of vertices as first polygon ring")?
+                    .iter()
+                    .map(|v| to_numbers(v).map(|c| [c[0], c[1]]))
+                    .collect::<Result<Vec<[f64; 2]>, String>>()?;
+                Region::Polygon {
+                    vertices,
+                    z_min: number("z_min")?.unwrap_or(f64::NEG_INFINITY),
+                    z_max: number("z_max")?.unwrap_or(f64::INFINITY),
+                }
+            }
+            Some("Point") => Region::Sphere {
+                center: to_vec3(coordinates)?,
+                radius: number("radius")?.ok_or("Expected the property 'radius' for a Point")?,
+            },
+            other => return Err(format!("Unsupported GeoJSON geometry {:?}", other)),
+        }
+    };
+
+    Ok(if properties["inverted"] == Value::Bool(true) { region.inverted() } else { region })
+}
+
+// Numbers of an array with at least two elements, e.g. the coordinates of a vertex
+fn to_numbers(json: &Value) -> Result<Vec<f64>, String> {
+    let values = json.as_array()
+        .filter(|values| values.len() >= 2)
+        .ok_or(format!("Expected an array of coordinates, got {}", json))?;
+    values.iter()
+        .map(|v| v.as_f64().ok_or(format!("Expected a number, got {}", v)))
+        .collect()
+}
+
+fn to_vec3(json: &Value) -> Result<[f64; 3], String> {
+    match to_numbers(json)?.as_slice() {
+        [x, y, z] => Ok([*x, *y, *z]),
+        _ => Err(format!("Expected three coordinates, got {}", json)),
+    }
+}
+
+#[cfg(test)]
+mod region_test {
+    use crate::region::{region_from_wkt, regions_from_geojson, Region};
+
+    #[test]
+    fn contains_points() {
+        let square = region_from_wkt("POLYGON ((0 0, 4 0, 4 4, 0 4, 0 0))").unwrap();
+        assert!(square.contains(&[1.0, 1.0, 100.0]));
+        assert!(!square.contains(&[5.0, 1.0, 0.0]));
+        assert!(square.clone().inverted().contains(&[5.0, 1.0, 0.0]));
+
+        let wall = region_from_wkt("POLYGON Z ((0 0 1, 4 0 1, 4 4 3, 0 4 3))").unwrap();
+        assert_eq!(wall, Region::Polygon { vertices: vec![[0.0, 0.0], [4.0, 0.0], [4.0, 4.0], [0.0, 4.0]], z_min: 1.0, z_max: 3.0 });
+        assert!(!wall.contains(&[1.0, 1.0, 0.0]));
+        assert_eq!(region_from_wkt(" polygon z( ( 0 0 1,4 0 1, 4 4 3 , 0 4 3 ) )"), Ok(wall.clone()));
+        assert_eq!(region_from_wkt("PolygonZ ((0 0 1, 4 0 1, 4 4 3, 0 4 3))"), Ok(wall));
+
+        // Box rotated by 45 degrees around z
+        let (s, c) = std::f64::consts::FRAC_PI_4.sin_cos();
+        let rotated = Region::OrientedBox { center: [0.0; 3], half_size: [1.0, 0.1, 1.0], rotation: [[c, -s, 0.0], [s, c, 0.0], [0.0, 0.0, 1.0]] };
+        assert!(rotated.contains(&[0.6, 0.6, 0.0]));
+        assert!(!rotated.contains(&[0.6, -0.6, 0.0]));
+        assert!(Region::aligned_box([0.0; 3], [1.0; 3]).contains(&[0.5, 0.5, 0.5]));
+    }
+
+    #[test]
+    fn reject_invalid_wkt() {
+        let err = region_from_wkt("MULTIPOLYGON (((0 0, 4 0, 4 4, 0 0)))").unwrap_err();
+        assert!(err.contains("Unsupported WKT geometry 'MULTIPOLYGON'"), "{}", err);
+        assert!(region_from_wkt("POLYGON").is_err());
+        assert!(region_from_wkt("POLYGON ((0 0, 4 x, 4 4))").is_err());
+        assert!(region_from_wkt("POLYGON Z ((0 0 1, 4 0, 4 4 3))").is_err());
+    }
+
+    #[test]
+    fn read_geojson() {
+        let regions = regions_from_geojson(r#"{
+            "type": "FeatureCollection",
+            "features": [
+                {"type": "Feature", "properties": {"name": "building", "z_min": 0.0, "z_max": 10.5},
+                 "geometry": {"type": "Polygon", "coordinates": [[[0, 0], [4, 0], [4, 4], [0, 0]]]}},
+                {"type": "Feature", "properties": {"radius": 2.5, "inverted": true},
+                 "geometry": {"type": "Point", "coordinates": [1.0, 2.0, -3e-1]}},
+                {"type": "Feature", "properties": {"center": [1, 1, 1], "half_size": [2, 1, 0.5]}, "geometry": null}
+            ]
+        }"#).unwrap();
+        assert_eq!(regions.len(), 3);
+        assert!(matches!(&regions[0], Region::Polygon { vertices, z_max, .. } if vertices.len() == 4 && *z_max == 10.5));
+        assert_eq!(regions[1], Region::Sphere { center: [1.0, 2.0, -0.3], radius: 2.5 }.inverted());
+        assert_eq!(regions[2], Region::aligned_box([-1.0, 0.0, 0.5], [3.0, 2.0, 1.5]));
+    }
+
+    #[test]
+    fn reject_invalid_geojson() {
+        let invalid = [
+            r#"{"type": "Feature", "geometry": {"type": "Point", "coordinates": [1, 2, 3]}"#,
+            r#"{"type": "FeatureCollection", "features": [1, 2,]}"#,
+            r#"{"type": "FeatureCollection"}"#,
+            r#"{"type": "Point", "coordinates": [1, 2, 3]}"#,
+            r#"{"type": "Polygon", "coordinates": [[[0, 0], [4], [4, 4]]]}"#,
+            r#"{"type": "LineString", "coordinates": [[0, 0], [4, 4]]}"#,
+            r#"{"type": "Feature", "properties": {"center": [1, 1], "half_size": [1, 1, 1]}, "geometry": null}"#,
+            r#"{"type": "Feature", "properties": {"radius": "2"}, "geometry": {"type": "Point", "coordinates": [1, 2, 3]}}"#,
+        ];
+        for text in invalid {
+            assert!(regions_from_geojson(text).is_err(), "Accepted {}", text);
+        }
+    }
+}
@@ -1,55 +1,429 @@
-use std::process::id;
-use linfa_linalg::cholesky::{SolveC, SolveCInplace};
-
 use linfa_linalg::svd::SVD;
-use ndarray::{Array1, Array2, Zip};
+use ndarray::{array, Array1, Array2, ArrayView1, Axis, s};
+
+use crate::cloud_view::CloudView;
+use crate::eigen3::SymmetricEigen3;
+
+/// Result of a single least squares adjustment of the six rigid-body transformation parameters
+/// alpha1, alpha2, alpha3 (rotation angles in radian), tx, ty, tz.
+pub struct RigidBodyTransformation {
+    pub h: Array2<f64>,
+    pub parameters: [f64; 6],
+    // A posteriori standard deviations of the parameters
+    pub uncertainties: [f64; 6],
+    // Point-to-plane distances after applying the transformation
+    pub residuals: Array1<f64>,
+    // Ratio of the largest to the smallest eigenvalue of the normal matrix A^T * A
+    pub condition_number: f64,
+}
+
+/// Error metric which is minimized by the adjustment.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ErrorMetric {
+    /// Distances of the moved points to the tangent planes of the fixed points (needs normals).
+    #[default]
+    PointToPlane,
+    /// Euclidean distances between the points, solved in closed form (Kabsch/Umeyama). Doesn't
+    /// need normals, e.g. for sparse or very noisy clouds.
+    PointToPoint,
+    /// Symmetric objective of Rusinkiewicz (2019), which uses the normals of both clouds and rotates
+    /// both points by half of the rotation. Converges faster and from worse initial positions.
+    Symmetric,
+    /// Generalized-ICP (Segal et al. 2009), i.e. Mahalanobis distances w.r.t. the covariances of both
+    /// points (plane-to-plane). Needs the normals of both clouds.
+    Gicp,
+    /// Colored ICP (Park et al. 2017), i.e. point-to-plane plus the differences of the intensities of
+    /// the moved points to the intensities of the fixed tangent planes, modeled by their color
+    /// gradients. Constrains in-plane shifts on textured planes. Needs colors of both clouds.
+    Colored {
+        /// Weight of the photometric term in [0, 1], the geometric term gets 1 - color_weight.
+        color_weight: f64,
+    },
+}
+
+impl ErrorMetric {
+    pub fn needs_normals(&self) -> bool {
+        *self != ErrorMetric::PointToPoint
+    }
+
+    pub fn needs_colors(&self) -> bool {
+        matches!(self, ErrorMetric::Colored { .. })
+    }
+
+    // Whether distance() returns signed distances, otherwise they are non-negative
+    pub fn signed_distance(&self) -> bool {
+        !matches!(self, ErrorMetric::PointToPoint)
+    }
+
+    pub fn needs_moved_normals(&self) -> bool {
+        matches!(self, ErrorMetric::Symmetric | ErrorMetric::Gicp)
+    }
+
+    // Distance of the corresponding points p1 (with normal n1) and p2 w.r.t. this metric
+    pub fn distance(&self, p1: [f64; 3], n1: [f64; 3], p2: [f64; 3]) -> f64 {
+        match self {
+            ErrorMetric::PointToPlane | ErrorMetric::Symmetric | ErrorMetric::Gicp | ErrorMetric::Colored { .. } => (0..3).map(|i| (p2[i] - p1[i]) * n1[i]).sum(),
+            ErrorMetric::PointToPoint => (0..3).map(|i| (p2[i] - p1[i]).powi(2)).sum::<f64>().sqrt(),
+        }
+    }
+
+    pub fn estimate(&self, pc1: &CloudView, pc2: &CloudView) -> RigidBodyTransformation {
+        match self {
+            ErrorMetric::PointToPlane => estimate_rigid_body_transformation(pc1, pc2),
+            ErrorMetric::PointToPoint => estimate_point_to_point_transformation(pc1, pc2),
+            ErrorMetric::Symmetric => estimate_symmetric_transformation(pc1, pc2),
+            ErrorMetric::Gicp => estimate_gicp_transformation(pc1, pc2),
+            ErrorMetric::Colored { color_weight } => estimate_colored_transformation(pc1, pc2, *color_weight),
+        }
+    }
+}
+
+pub fn estimate_rigid_body_transformation(pc1: &CloudView, pc2: &CloudView) -> RigidBodyTransformation {
+    assert_eq!(pc1.len(), pc2.len());
+    let mut m_a: Array2<f64> = Array2::default((pc1.len(), 6));
+    let mut v_l: Array1<f64> = Array1::default(pc1.len());
+
+    for (i, (([x_pc1, y_pc1, z_pc1], n1), p2)) in pc1.points().zip(pc1.normals()).zip(pc2.points()).enumerate() {
+        let [nx_pc1, ny_pc1, nz_pc1] = n1;
+        let [x_pc2, y_pc2, z_pc2] = p2;
+
+        m_a.row_mut(i).assign(&ArrayView1::from(&design_row(p2, n1)));
+        v_l[[i]] = nx_pc1 * (x_pc1 - x_pc2) + ny_pc1 * (y_pc1 - y_pc2) + nz_pc1 * (z_pc1 - z_pc2);
+    }
+
+    solve_least_squares(&m_a, &v_l)
+}
+
+// Symmetric point-to-plane adjustment (Rusinkiewicz 2019) of the transformation which maps pc2 onto
+// pc1. Both clouds need normals. The moved point p is rotated by R, the fixed point q by R^-1 and the
+// residual (R * p - R^-1 * q + t) * (n_p + n_q) is minimized, i.e. the final transformation is
+// R * T(t) * R with R the half rotation.
+pub fn estimate_symmetric_transformation(pc1: &CloudView, pc2: &CloudView) -> RigidBodyTransformation {
+    assert_eq!(pc1.len(), pc2.len());
+    let mut m_a: Array2<f64> = Array2::default((pc1.len(), 6));
+    let mut v_l: Array1<f64> = Array1::default(pc1.len());
+
+    for (i, ((q, n_q), (p, n_p))) in pc1.points().zip(pc1.normals()).zip(pc2.points().zip(pc2.normals())).enumerate() {
+        // The orientation of the normals is arbitrary
+        let sign = if (0..3).map(|k| n_p[k] * n_q[k]).sum::<f64>() < 0.0 { -1.0 } else { 1.0 };
+        let n = [0, 1, 2].map(|k| n_q[k] + sign * n_p[k]);
+        m_a.row_mut(i).assign(&ArrayView1::from(&design_row([0, 1, 2].map(|k| p[k] + q[k]), n)));
+        v_l[[i]] = (0..3).map(|k| (q[k] - p[k]) * n[k]).sum();
+    }
+    let adjustment = solve_least_squares(&m_a, &v_l);
+
+    // The solution is a~ = axis * tan(theta) and t~ = t / cos(theta) with theta the half rotation angle
+    let x = adjustment.parameters;
+    let tan_theta = (x[0] * x[0] + x[1] * x[1] + x[2] * x[2]).sqrt();
+    let theta = tan_theta.atan();
+    let r_half = if tan_theta > 0.0 {
+        axis_angle_to_rotation_matrix([x[0] / tan_theta, x[1] / tan_theta, x[2] / tan_theta], theta)
+    } else {
+        Array2::eye(3)
+    };
+    let t = array![x[3], x[4], x[5]] * theta.cos();
+    let h_half: Array2<f64> = homogeneous_transformation_matrix(&r_half, &Array1::zeros(3));
+    let h = h_half.dot(&homogeneous_transformation_matrix(&Array2::eye(3), &t)).dot(&h_half);
+
+    let angles = rotation_matrix_to_euler_angles(&h.slice(s![..3, ..3]).to_owned());
+    let u = adjustment.uncertainties;
+    RigidBodyTransformation {
+        parameters: [angles[0], angles[1], angles[2], h[[0, 3]], h[[1, 3]], h[[2, 3]]],
+        h,
+        // The rotation angles are (to first order) twice the estimated half angles
+        uncertainties: [2.0 * u[0], 2.0 * u[1], 2.0 * u[2], u[3], u[4], u[5]],
+        residuals: adjustment.residuals,
+        condition_number: adjustment.condition_number,
+    }
+}
+
+// Rotation about the unit vector axis by angle (in radian), Rodrigues' formula
+pub fn axis_angle_to_rotation_matrix(axis: [f64; 3], angle: f64) -> Array2<f64> {
+    let [x, y, z] = axis;
+    let k = array![[0.0, -z, y], [z, 0.0, -x], [-y, x, 0.0]];
+    Array2::eye(3) + &k * angle.sin() + k.dot(&k) * (1.0 - angle.cos())
+}
+
+// Closed form solution of the transformation which maps pc2 onto pc1 with minimal point-to-point
+// distances (Kabsch/Umeyama without scale). The residuals are the distances after the transformation.
+pub fn estimate_point_to_point_transformation(pc1: &CloudView, pc2: &CloudView) -> RigidBodyTransformation {
+    assert_eq!(pc1.len(), pc2.len());
+    let n = pc1.len();
+    let to_array = |view: &CloudView| -> Array2<f64> {
+        Array2::from_shape_vec((n, 3), view.points().flatten().collect()).unwrap()
+    };
+    let (x1, x2) = (to_array(pc1), to_array(pc2));
+    let (c1, c2) = (x1.mean_axis(Axis(0)).unwrap(), x2.mean_axis(Axis(0)).unwrap());
+
+    // Cross-covariance of the centered points, R = V * diag(1, 1, det(V * U^T)) * U^T
+    let cross_covariance = (&x2 - &c2).t().dot(&(&x1 - &c1));
+    let (u, _, vt) = cross_covariance.svd(true, true).expect("Could not calculate SVD");
+    let (u, v) = (u.unwrap(), vt.unwrap().reversed_axes());
+    let mut d: Array2<f64> = Array2::eye(3);
+    d[[2, 2]] = determinant3(&v.dot(&u.t())).signum();
+    let r = v.dot(&d).dot(&u.t());
+    let t = &c1 - &r.dot(&c2);
+
+    // The uncertainties are those of the linearized adjustment at the solution, i.e. with the
+    // transformed points of pc2. Each correspondence contributes one row per coordinate.
+    let x2_transformed = x2.dot(&r.t()) + &t;
+    let mut m_a: Array2<f64> = Array2::zeros((3 * n, 6));
+    let mut v_l: Array1<f64> = Array1::zeros(3 * n);
+    for (i, (p1, p2)) in x1.outer_iter().zip(x2_transformed.outer_iter()).enumerate() {
+        for (k, row) in point_to_point_rows([p2[0], p2[1], p2[2]]).iter().enumerate() {
+            m_a.row_mut(3 * i + k).assign(&ArrayView1::from(row));
+        }
+        v_l.slice_mut(s![3 * i..3 * i + 3]).assign(&(&p1 - &p2));
+    }
+    let adjustment = solve_least_squares(&m_a, &v_l);
+
+    let angles = rotation_matrix_to_euler_angles(&r);
+    RigidBodyTransformation {
+        h: homogeneous_transformation_matrix(&r, &t),
+        parameters: [angles[0], angles[1], angles[2], t[0], t[1], t[2]],
+        uncertainties: adjustment.uncertainties,
+        residuals: (&x1 - &x2_transformed).map_axis(Axis(1), |d| d.dot(&d).sqrt()),
+        condition_number: adjustment.condition_number,
+    }
+}
+
+// Generalized-ICP adjustment of the transformation which maps pc2 onto pc1. The coordinate differences
+// of each correspondence are weighted with the inverse of the sum of both covariances (the rotation
+// of the covariance of pc2 is neglected, as pc2 is already close to its final position).
+pub fn estimate_gicp_transformation(pc1: &CloudView, pc2: &CloudView) -> RigidBodyTransformation {
+    assert_eq!(pc1.len(), pc2.len());
+    let mut m_a: Array2<f64> = Array2::zeros((3 * pc1.len(), 6));
+    let mut v_l: Array1<f64> = Array1::zeros(3 * pc1.len());
+
+    for i in 0..pc1.len() {
+        let (p1, p2) = (pc1.point(i), pc2.point(i));
+        let (c1, c2) = (pc1.covariance(i), pc2.covariance(i));
+        let sum = [0, 1, 2].map(|r| [0, 1, 2].map(|c| c1[r][c] + c2[r][c]));
+
+        // Whitening with the inverse square root of the covariance V * diag(1 / sqrt(ev)) * V^T
+        let eigen = SymmetricEigen3::new(&sum);
+        let v = Array2::from_shape_fn((3, 3), |(r, c)| eigen.vectors[c][r]);
+        let inv_sqrt = Array1::from_iter(eigen.values.iter().map(|ev| 1.0 / ev.max(f64::EPSILON).sqrt()));
+        let w = (&v * &inv_sqrt).dot(&v.t());
+
+        let rows = Array2::from_shape_fn((3, 6), |(r, c)| point_to_point_rows(p2)[r][c]);
+        m_a.slice_mut(s![3 * i..3 * i + 3, ..]).assign(&w.dot(&rows));
+        let diff = Array1::from_iter((0..3).map(|k| p1[k] - p2[k]));
+        v_l.slice_mut(s![3 * i..3 * i + 3]).assign(&w.dot(&diff));
+    }
+
+    solve_least_squares(&m_a, &v_l)
+}
+
+// Colored ICP adjustment of the transformation which maps pc2 onto pc1. pc1 needs normals and color
+// gradients. Each correspondence contributes a point-to-plane row and a photometric row, i.e. the
+// intensity of the tangent plane of the fixed point at the projection of the moved point minus the
+// intensity of the moved point. The rows are weighted with sqrt(1 - color_weight) and sqrt(color_weight).
+pub fn estimate_colored_transformation(pc1: &CloudView, pc2: &CloudView, color_weight: f64) -> RigidBodyTransformation {
+    assert_eq!(pc1.len(), pc2.len());
+    assert!((0.0..=1.0).contains(&color_weight), "Color weight must be within [0, 1]");
+    let (w_geometric, w_color) = ((1.0 - color_weight).sqrt(), color_weight.sqrt());
+    let mut m_a: Array2<f64> = Array2::zeros((2 * pc1.len(), 6));
+    let mut v_l: Array1<f64> = Array1::zeros(2 * pc1.len());
+
+    for i in 0..pc1.len() {
+        let (p, n, d) = (pc1.point(i), pc1.normal(i), pc1.color_gradient(i));
+        let q = pc2.point(i);
+        let geometric_row = design_row(q, n);
+        m_a.row_mut(2 * i).assign(&(w_geometric * &ArrayView1::from(&geometric_row)));
+        v_l[2 * i] = w_geometric * (0..3).map(|k| n[k] * (p[k] - q[k])).sum::<f64>();
+
+        // The gradient lies within the tangent plane, so the derivative of the intensity at the
+        // projected point w.r.t. the moved point is d itself
+        let height = (0..3).map(|k| n[k] * (q[k] - p[k])).sum::<f64>();
+        let offset = (0..3).map(|k| d[k] * (q[k] - height * n[k] - p[k])).sum::<f64>();
+        let color_residual = pc1.intensity(i) + offset - pc2.intensity(i);
+        let rows = point_to_point_rows(q);
+        let color_row: [f64; 6] = [0, 1, 2, 3, 4, 5].map(|c| (0..3).map(|k| d[k] * rows[k][c]).sum());
+        m_a.row_mut(2 * i + 1).assign(&(w_color * &ArrayView1::from(&color_row)));
+        v_l[2 * i + 1] = -w_color * color_residual;
+    }
+
+    solve_least_squares(&m_a, &v_l)
+}
+
+// Rows of the design matrix of the coordinate differences of point p, i.e. the derivatives of x, y, z
+// w.r.t. alpha1, alpha2, alpha3, tx, ty, tz
+pub(crate) fn point_to_point_rows(p: [f64; 3]) -> [[f64; 6]; 3] {
+    let [x, y, z] = p;
+    [
+        [0.0, z, -y, 1.0, 0.0, 0.0],
+        [-z, 0.0, x, 0.0, 1.0, 0.0],
+        [y, -x, 0.0, 0.0, 0.0, 1.0],
+    ]
+}
+
+fn determinant3(m: &Array2<f64>) -> f64 {
+    m[[0, 0]] * (m[[1, 1]] * m[[2, 2]] - m[[1, 2]] * m[[2, 1]])
+        - m[[0, 1]] * (m[[1, 0]] * m[[2, 2]] - m[[1, 2]] * m[[2, 0]])
+        + m[[0, 2]] * (m[[1, 0]] * m[[2, 1]] - m[[1, 1]] * m[[2, 0]])
+}
+
+// Row of the design matrix A of a point-to-plane correspondence, i.e. the derivatives of the
+// distance of point p to the plane with normal n w.r.t. alpha1, alpha2, alpha3, tx, ty, tz
+pub(crate) fn design_row(p: [f64; 3], n: [f64; 3]) -> [f64; 6] {
+    [
+        -p[2] * n[1] + p[1] * n[2],
+        p[2] * n[0] - p[0] * n[2],
+        -p[1] * n[0] + p[0] * n[1],
+        n[0],
+        n[1],
+        n[2],
+    ]
+}
+
+// Solves A * x = l for the linearized rigid-body parameters x via SVD
+pub(crate) fn solve_least_squares(m_a: &Array2<f64>, v_l: &Array1<f64>) -> RigidBodyTransformation {
+    let (u, sigma, vt) = m_a.svd(true, true).expect("Could not calculate SVD");
+    let u = u.unwrap();
+    let vt = vt.unwrap();
+
+    // The singular values of A are the square roots of the eigenvalues of A^T * A
+    let s_max = sigma.fold(0.0_f64, |a, b| a.max(*b));
+    let s_min = sigma.fold(f64::INFINITY, |a, b| a.min(*b));
+    if s_min.is_nan() || s_min <= s_max * m_a.nrows().max(6) as f64 * f64::EPSILON {
+        panic!(
+            "Rigid-body parameters are not determined by the {} correspondences (singular values {:e} to {:e}). \
+            The correspondences probably lie on a single plane or line.",
+            m_a.nrows(), s_min, s_max
+        );
+    }
+    let condition_number = (s_max / s_min).powi(2);
+
+    let x = vt.t().dot(&(u.t().dot(v_l) / &sigma));
+    let residuals = m_a.dot(&x) - v_l;
+
+    // Cofactor matrix Qxx = (A^T * A)^-1 = V * S^-2 * V^T
+    let q_xx = vt.t().dot(&(&vt / &sigma.mapv(|s| s * s).insert_axis(Axis(1))));
+    let redundancy = (m_a.nrows() as f64 - 6.0).max(1.0);
+    let s0_squared = residuals.dot(&residuals) / redundancy;
+
+    let parameters = [x[0], x[1], x[2], x[3], x[4], x[5]];
+    RigidBodyTransformation {
+        h: homogeneous_transformation_matrix(&euler_angles_to_rotation_matrix(x[0], x[1], x[2]), &x.slice(s![3..]).to_owned()),
+        parameters,
+        uncertainties: [0, 1, 2, 3, 4, 5].map(|i| (s0_squared * q_xx[[i, i]]).sqrt()),
+        residuals,
+        condition_number,
+    }
+}
+
+pub fn euler_angles_to_rotation_matrix(alpha1: f64, alpha2: f64, alpha3: f64) -> Array2<f64> {
+    let (s1, c1) = alpha1.sin_cos();
+    let (s2, c2) = alpha2.sin_cos();
+    let (s3, c3) = alpha3.sin_cos();
+    array![
+        [c2 * c3, -c2 * s3, s2],
+        [c1 * s3 + s1 * s2 * c3, c1 * c3 - s1 * s2 * s3, -s1 * c2],
+        [s1 * s3 - c1 * s2 * c3, s1 * c3 + c1 * s2 * s3, c1 * c2],
+    ]
+}
+
+pub fn rotation_matrix_to_euler_angles(r: &Array2<f64>) -> [f64; 3] {
+    [
+        f64::atan2(-r[[1, 2]], r[[2, 2]]),
+        r[[0, 2]].clamp(-1.0, 1.0).asin(),
+        f64::atan2(-r[[0, 1]], r[[0, 0]]),
+    ]
+}
 
-use crate::pointcloud::PointCloud;
+pub fn homogeneous_transformation_matrix(r: &Array2<f64>, t: &Array1<f64>) -> Array2<f64> {
+    let mut h = Array2::eye(4);
+    h.slice_mut(s![..3, ..3]).assign(r);
+    h.slice_mut(s![..3, 3]).assign(t);
+    h
+}
 
-pub fn estimate_rigid_body_transformation(pc1: &PointCloud, pc2: &PointCloud) {
-    let mut m_a: Array2<f64> = Array2::default((pc1.point_amount(), 6));
-    let mut v_l: Array1<f64> = Array1::default((pc1.point_amount()));
 
-    let all_idx: Vec<usize> = (0..pc1.point_amount()).collect();
-    let mut idx = all_idx.into_iter();
+#[cfg(test)]
+mod rigid_body_transformation_test {
+    use ndarray::{array, Array1, Array2};
 
-    Zip::from(pc1.points().outer_iter())
-        .and(pc1.normals().outer_iter())
-        .and(pc2.points().outer_iter())
-        .for_each(|p1, n1, p2| {
-            let x_pc1 = p1[[0]];
-            let y_pc1 = p1[[1]];
-            let z_pc1 = p1[[2]];
+    use crate::pointcloud::PointCloud;
+    use crate::rigid_body_transformation::{
+        design_row, ErrorMetric, euler_angles_to_rotation_matrix, homogeneous_transformation_matrix,
+        rotation_matrix_to_euler_angles, solve_least_squares,
+    };
 
-            let nx_pc1 = n1[[0]];
-            let ny_pc1 = n1[[1]];
-            let nz_pc1 = n1[[2]];
+    #[test]
+    fn euler_angles_round_trip() {
+        let r = euler_angles_to_rotation_matrix(0.1, -0.2, 0.3);
+        let angles = rotation_matrix_to_euler_angles(&r);
+        assert_float_absolute_eq!(angles[0], 0.1, 1e-12);
+        assert_float_absolute_eq!(angles[1], -0.2, 1e-12);
+        assert_float_absolute_eq!(angles[2], 0.3, 1e-12);
 
-            let x_pc2 = p2[[0]];
-            let y_pc2 = p2[[1]];
-            let z_pc2 = p2[[2]];
+        let should_be_zero = r.t().dot(&r) - Array2::<f64>::eye(3);
+        assert!(should_be_zero.iter().all(|v| v.abs() < 1e-12));
+    }
 
-            let i = idx.next().unwrap();
+    #[test]
+    fn point_to_point_solves_in_closed_form() {
+        let points = vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 3.0, 1.0, 1.0, 1.0, -1.0, 0.5, 2.0];
+        let fixed = PointCloud::new(points.clone());
+        let mut moved = PointCloud::new(points);
+        let h = homogeneous_transformation_matrix(&euler_angles_to_rotation_matrix(0.3, -0.5, 1.2), &array![1.0, -2.0, 0.5]);
+        moved.transform(&h);
 
-            m_a[[i, 0]] = -z_pc2 * ny_pc1 + y_pc2 * nz_pc1;
-            m_a[[i, 1]] = z_pc2 * nx_pc1 - x_pc2 * nz_pc1;
-            m_a[[i, 2]] = -y_pc2 * nx_pc1 + x_pc2 * ny_pc1;
-            m_a[[i, 3]] = nx_pc1;
-            m_a[[i, 4]] = ny_pc1;
-            m_a[[i, 5]] = nz_pc1;
+        // No normals are needed and the (large) transformation is found without iterations
+        let res = ErrorMetric::PointToPoint.estimate(&fixed.view(), &moved.view());
+        let should_be_identity = res.h.dot(&h);
+        assert!((should_be_identity - Array2::<f64>::eye(4)).iter().all(|v| v.abs() < 1e-12));
+        assert!(res.residuals.iter().all(|v| v.abs() < 1e-12));
+        assert!(res.condition_number.is_finite());
+    }
 
-            v_l[[i]] = nx_pc1 * (x_pc1 - x_pc2) + ny_pc1 * (y_pc1 - y_pc2) + nz_pc1 * (z_pc1 - z_pc2);
-        });
+    // Points on an ellipsoid with their exact normals
+    fn ellipsoid() -> PointCloud {
+        let mut points = Vec::new();
+        for i in 1..12 {
+            for j in 0..24 {
+                let (theta, phi) = (i as f64 * std::f64::consts::PI / 12.0, j as f64 * std::f64::consts::PI / 12.0);
+                points.extend_from_slice(&[theta.sin() * phi.cos(), 1.5 * theta.sin() * phi.sin(), 0.7 * theta.cos()]);
+            }
+        }
+        let normals: Vec<f64> = points.chunks(3)
+            .flat_map(|p| {
+                let n = [p[0], p[1] / 2.25, p[2] / 0.49];
+                let len = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+                n.map(|v| v / len)
+            })
+            .collect();
+        PointCloud::with_normals(points, normals)
+    }
 
-    let svd = m_a.svd(true, true).expect("Could not calculate SVD");
+    #[test]
+    fn symmetric_and_gicp_recover_transformation() {
+        let h = homogeneous_transformation_matrix(&euler_angles_to_rotation_matrix(0.02, -0.03, 0.05), &array![0.01, 0.02, -0.01]);
+        for metric in [ErrorMetric::Symmetric, ErrorMetric::Gicp] {
+            let fixed = ellipsoid();
+            let mut moved = ellipsoid();
+            moved.transform(&h);
 
-    //let res = svd.2.unwrap().solvec(v_l);
-    /*let u = x_svd.0.unwrap();
+            let mut h_total: Array2<f64> = Array2::eye(4);
+            for _ in 0..3 {
+                let res = metric.estimate(&fixed.view(), &moved.view());
+                moved.transform(&res.h);
+                h_total = res.h.dot(&h_total);
+            }
+            let should_be_identity = h_total.dot(&h);
+            assert!((should_be_identity - Array2::<f64>::eye(4)).iter().all(|v| v.abs() < 1e-6), "{:?}", metric);
+        }
+    }
 
-    let alpha1 = u[[0]];
-    let alpha2 = u[[1]];
-    let alpha3 = u[[2]];
-    let tx = u[[3]];
-    let ty = u[[4]];
-    let tz = u[[5]];*/
-}
\ No newline at end of file
+    #[test]
+    #[should_panic(expected = "Rigid-body parameters are not determined")]
+    fn singular_design_matrix() {
+        // Points of a single plane with equal normals do not constrain the shift within the plane
+        let rows: Vec<f64> = (0..20)
+            .flat_map(|i| design_row([i as f64 % 5.0, (i / 5) as f64, 0.0], [0.0, 0.0, 1.0]))
+            .collect();
+        let m_a = Array2::from_shape_vec((20, 6), rows).unwrap();
+        solve_least_squares(&m_a, &Array1::zeros(20));
+    }
+}
@@ -0,0 +1,394 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+use linfa_linalg::eigh::EighInto;
+use ndarray::{Array, Array2, ArrayView1};
+use rand::seq::index::sample;
+use rayon::prelude::*;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use crate::cloud_view::CloudView;
+use crate::rigid_body_transformation::design_row;
+use crate::voxel_grid::voxel_members;
+
+/// Strategy to select a fixed number of points (e.g. the correspondences) from a point cloud.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SamplingStrategy {
+    /// Evenly spaced indices, i.e. the selection depends on the point order in the file.
+    Stride,
+    /// Points are bucketed by the direction of their normal vector (`bins` x `bins` buckets over the
+    /// hemisphere) and sampled evenly across the buckets, see Rusinkiewicz & Levoy (2001).
+    /// Requires estimated normals.
+    NormalSpace { bins: usize },
+    /// Uniform random sample, reproducible by `seed`.
+    Random { seed: u64 },
+    /// Random sample stratified over a voxel grid, whose cell size is chosen such that about as many
+    /// cells are occupied as points are requested. Reproducible by `seed`.
+    Stratified { seed: u64 },
+    /// Points are chosen greedily such that the smallest eigenvalue of the normal matrix of the
+    /// point-to-plane adjustment is maximized, i.e. all six parameters are constrained, see
+    /// Gelfand et al. (2003). Requires estimated normals.
+    Stability,
+    /// Iteratively the point farthest from all points chosen so far, starting with the first point.
+    /// The result is spread evenly over the object, independent of the point density.
+    FarthestPoint,
+}
+
+impl SamplingStrategy {
+    pub fn seed(&self) -> Option<u64> {
+        match *self {
+            SamplingStrategy::Random { seed } | SamplingStrategy::Stratified { seed } => Some(seed),
+            _ => None,
+        }
+    }
+}
+
+// Returns n evenly spaced positions within 0..len
+pub(crate) fn stride_indices(len: usize, n: usize) -> Vec<usize> {
+    if n >= len {
+        return (0..len).collect();
+    }
+    Array::<f64, _>::linspace(0., (len - 1) as f64, n)
+        .iter()
+        .map(|idx| idx.floor() as usize)
+        .collect()
+}
+
+// Returns the (sorted) positions of n points sampled evenly across the normal buckets. Points
+// without a valid normal are never selected; if no point has one, stride sampling is used instead.
+pub(crate) fn normal_space_indices(view: &CloudView, n: usize, bins: usize) -> Vec<usize> {
+    let missing = view.normals().filter(|normal| normal.iter().any(|v| v.is_nan())).count();
+    if missing == view.len() {
+        println!("No normals estimated for normal-space sampling -> fall back to stride sampling");
+        return stride_indices(view.len(), n);
+    }
+    if missing > 0 {
+        println!("Normal-space sampling skips {} of {} points without normal", missing, view.len());
+    }
+
+    let bins = bins.max(1);
+    let mut buckets: Vec<Vec<usize>> = vec![Vec::new(); bins * bins];
+
+    for (i, normal) in view.normals().enumerate() {
+        if normal.iter().any(|v| v.is_nan()) {
+            continue;
+        }
+        // The sign of a normal is arbitrary, hence all normals are mapped to the upper hemisphere
+        let sign = if normal[2] < 0.0 { -1.0 } else { 1.0 };
+        let (nx, ny, nz) = (sign * normal[0], sign * normal[1], sign * normal[2]);
+
+        let theta = nz.clamp(-1.0, 1.0).acos() / (PI / 2.0);
+        let phi = (ny.atan2(nx) + PI) / (2.0 * PI);
+        let bin_theta = ((theta * bins as f64) as usize).min(bins - 1);
+        let bin_phi = ((phi * bins as f64) as usize).min(bins - 1);
+        buckets[bin_theta * bins + bin_phi].push(i);
+    }
+
+    // Distribute the samples round-robin, so that each non-empty bucket contributes
+    let mut quota = vec![0; buckets.len()];
+    let mut remaining = n.min(buckets.iter().map(|b| b.len()).sum());
+    while remaining > 0 {
+        for (bucket, q) in buckets.iter().zip(quota.iter_mut()) {
+            if remaining > 0 && *q < bucket.len() {
+                *q += 1;
+                remaining -= 1;
+            }
+        }
+    }
+
+    let mut idx: Vec<usize> = buckets.iter()
+        .zip(quota)
+        .flat_map(|(bucket, q)| stride_indices(bucket.len(), q).into_iter().map(|i| bucket[i]))
+        .collect();
+    idx.sort_unstable();
+    idx
+}
+
+pub(crate) fn random_indices(len: usize, n: usize, seed: u64) -> Vec<usize> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut idx = sample(&mut rng, len, n.min(len)).into_vec();
+    idx.sort_unstable();
+    idx
+}
+
+pub(crate) fn stratified_indices(view: &CloudView, n: usize, seed: u64) -> Vec<usize> {
+    let len = view.len();
+    if n >= len {
+        return (0..len).collect();
+    }
+
+    // Bisection (on a log scale) of the cell size, until about n cells are occupied
+    let (min, max) = view.points().fold(([f64::MAX; 3], [f64::MIN; 3]), |(min, max), p| {
+        ([0, 1, 2].map(|axis| min[axis].min(p[axis])), [0, 1, 2].map(|axis| max[axis].max(p[axis])))
+    });
+    let extent = (0..3).map(|axis| max[axis] - min[axis]).fold(0.0, f64::max).max(f64::EPSILON);
+    let (mut lower, mut upper) = (extent * 1e-6, extent * 2.0);
+    let mut cells = voxel_members(view, upper);
+    for _ in 0..30 {
+        let size = (lower * upper).sqrt();
+        let candidate = voxel_members(view, size);
+        if candidate.len() > n {
+            lower = size;
+        } else {
+            upper = size;
+            cells = candidate;
+        }
+        if cells.len() * 10 >= n * 9 {
+            break;
+        }
+    }
+
+    // Draw points without replacement round-robin over the shuffled cells
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    cells.shuffle(&mut rng);
+    cells.iter_mut().for_each(|cell| cell.shuffle(&mut rng));
+
+    let mut idx = Vec::with_capacity(n);
+    let mut round = 0;
+    while idx.len() < n {
+        for cell in cells.iter().filter(|cell| round < cell.len()) {
+            if idx.len() < n {
+                idx.push(cell[round]);
+            }
+        }
+        round += 1;
+    }
+    idx.sort_unstable();
+    idx
+}
+
+// Returns the (sorted) positions of n points chosen by covariance sampling. Points without a valid
+// normal are never selected.
+pub(crate) fn stability_indices(view: &CloudView, n: usize) -> Vec<usize> {
+    let valid: Vec<usize> = (0..view.len())
+        .filter(|i| view.normal(*i).iter().all(|v| !v.is_nan()))
+        .collect();
+    if n >= valid.len() {
+        return valid;
+    }
+
+    // Rows of the design matrix w.r.t. the centroid, with the rotations scaled to unit lever arm so
+    // that rotations and translations are comparable
+    let centroid = [0, 1, 2].map(|axis| valid.iter().map(|i| view.point(*i)[axis]).sum::<f64>() / valid.len() as f64);
+    let centered = |i: usize| {
+        let p = view.point(i);
+        [0, 1, 2].map(|axis| p[axis] - centroid[axis])
+    };
+    let scale = valid.iter()
+        .map(|i| centered(*i).iter().map(|c| c * c).sum::<f64>().sqrt())
+        .sum::<f64>() / valid.len() as f64;
+    let rows: Vec<[f64; 6]> = valid.iter().map(|i| {
+        let mut row = design_row(centered(*i), view.normal(*i));
+        row[..3].iter_mut().for_each(|r| *r /= scale.max(f64::EPSILON));
+        row
+    }).collect();
+
+    // Always add the point which best constrains the currently weakest direction, i.e. the
+    // eigenvector of the smallest eigenvalue of the normal matrix of the points chosen so far
+    let mut normal_matrix: Array2<f64> = Array2::eye(6) * 1e-9;
+    let mut used = vec![false; rows.len()];
+    let mut idx = Vec::with_capacity(n);
+    while idx.len() < n {
+        let (values, vectors) = normal_matrix.clone().eigh_into().expect("Could not calculate eigenvectors");
+        let weakest = (0..6).min_by(|a, b| values[*a].total_cmp(&values[*b])).unwrap();
+        let direction = vectors.column(weakest);
+
+        let chosen = (0..rows.len())
+            .into_par_iter()
+            .filter(|i| !used[*i])
+            .map(|i| (ArrayView1::from(&rows[i]).dot(&direction).abs(), i))
+            .max_by(|a, b| a.0.total_cmp(&b.0).then(b.1.cmp(&a.1)))
+            .unwrap()
+            .1;
+        used[chosen] = true;
+        let row = ArrayView1::from(&rows[chosen]);
+        normal_matrix += &outer(row, row);
+        idx.push(valid[chosen]);
+    }
+    idx.sort_unstable();
+    idx
+}
+
+// Returns the positions of n points chosen by farthest point sampling
+pub(crate) fn farthest_point_indices(view: &CloudView, n: usize) -> Vec<usize> {
+    let len = view.len();
+    if n >= len {
+        return (0..len).collect();
+    }
+
+    // Squared distance of every point to its nearest chosen point
+    let mut sq_dists = vec![f64::INFINITY; len];
+    let mut idx = Vec::with_capacity(n);
+    let mut chosen = 0;
+    while idx.len() < n {
+        idx.push(chosen);
+        let c = view.point(chosen);
+        chosen = sq_dists
+            .par_iter_mut()
+            .enumerate()
+            .map(|(i, d)| {
+                let p = view.point(i);
+                *d = d.min((0..3).map(|axis| (p[axis] - c[axis]).powi(2)).sum());
+                (*d, i)
+            })
+            .max_by(|a, b| a.0.total_cmp(&b.0).then(b.1.cmp(&a.1)))
+            .unwrap()
+            .1;
+    }
+    idx.sort_unstable();
+    idx
+}
+
+// Returns the (sorted) positions of a subset of points, in which no two points are closer than
+// radius. Points are visited in order and kept if there is no kept point within radius.
+pub(crate) fn poisson_disk_indices(view: &CloudView, radius: f64) -> Vec<usize> {
+    assert!(radius > 0.0, "radius must be > 0");
+
+    // Kept points by grid cell; cells have the size of the radius, so only the 27 cells around a
+    // point have to be checked
+    let mut cells: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+    let mut idx = Vec::new();
+    for (i, p) in view.points().enumerate() {
+        let key = [0, 1, 2].map(|axis| (p[axis] / radius).floor() as i64);
+        let too_close = (-1..=1).any(|dx| (-1..=1).any(|dy| (-1..=1).any(|dz| {
+            cells.get(&[key[0] + dx, key[1] + dy, key[2] + dz]).is_some_and(|kept| {
+                kept.iter().any(|j| (0..3).map(|axis| (p[axis] - view.point(*j)[axis]).powi(2)).sum::<f64>() < radius * radius)
+            })
+        })));
+        if !too_close {
+            cells.entry(key).or_default().push(i);
+            idx.push(i);
+        }
+    }
+    idx
+}
+
+fn outer(a: ArrayView1<f64>, b: ArrayView1<f64>) -> Array2<f64> {
+    Array2::from_shape_fn((a.len(), b.len()), |(i, j)| a[i] * b[j])
+}
+
+
+#[cfg(test)]
+mod sampling_test {
+    use linfa_linalg::eigh::EighInto;
+    use ndarray::Array2;
+
+    use crate::pointcloud::PointCloud;
+    use crate::rigid_body_transformation::design_row;
+    use crate::sampling::{farthest_point_indices, normal_space_indices, poisson_disk_indices, random_indices, stability_indices,
+                          stratified_indices, stride_indices};
+
+    #[test]
+    fn normal_space_sampling_keeps_small_features() {
+        // A large floor followed by a small wall at the end of the file
+        let normals = Array2::from_shape_fn((1000, 3), |(i, axis)| {
+            match (i < 990, axis) {
+                (true, 2) | (false, 0) => 1.0,
+                _ => 0.0,
+            }
+        });
+
+        let stride = stride_indices(1000, 20);
+        assert_eq!(stride.iter().filter(|i| **i >= 990).count(), 1);
+
+        let cloud = PointCloud::with_normals(vec![0.0; 3000], normals.into_raw_vec());
+        let idx = normal_space_indices(&cloud.view(), 20, 4);
+        assert_eq!(idx.len(), 20);
+        assert_eq!(idx.iter().filter(|i| **i >= 990).count(), 10);
+
+        // Without normals, the points are sampled by stride
+        let cloud = PointCloud::new(vec![0.0; 3000]);
+        assert_eq!(normal_space_indices(&cloud.view(), 20, 4), stride);
+    }
+
+    #[test]
+    fn seeded_sampling_is_reproducible() {
+        assert_eq!(random_indices(1000, 50, 7), random_indices(1000, 50, 7));
+        assert_ne!(random_indices(1000, 50, 7), random_indices(1000, 50, 8));
+
+        // Dense cluster at the origin plus a sparse line of points
+        let points = Array2::from_shape_fn((1000, 3), |(i, axis)| {
+            if i < 900 { (i % 10) as f64 * 1e-3 * axis as f64 } else { (i - 900) as f64 * (axis == 0) as u8 as f64 }
+        });
+        let cloud = PointCloud::new(points.into_raw_vec());
+        let idx = stratified_indices(&cloud.view(), 50, 7);
+        assert_eq!(idx.len(), 50);
+        assert_eq!(idx, stratified_indices(&cloud.view(), 50, 7));
+        assert!(idx.iter().filter(|i| **i >= 900).count() >= 40);
+    }
+
+    #[test]
+    fn stability_sampling_constrains_corridor() {
+        // Floor and side walls of a corridor along x, closed by a small wall at its end
+        // (which is stored between the floor and the side walls)
+        let mut points = Vec::new();
+        let mut normals = Vec::new();
+        let mut add = |p: [f64; 3], n: [f64; 3]| {
+            points.extend_from_slice(&p);
+            normals.extend_from_slice(&n);
+        };
+        for i in 0..1000 {
+            add([(i / 10) as f64 * 0.2, (i % 10) as f64 * 0.2, 0.0], [0.0, 0.0, 1.0]);
+        }
+        for i in 0..10 {
+            add([20.0, i as f64 * 0.2, 1.0], [-1.0, 0.0, 0.0]);
+        }
+        for i in 0..400 {
+            let (x, z) = ((i / 4) as f64 * 0.2, (i % 4) as f64 * 0.5 + 0.5);
+            add([x, 0.0, z], [0.0, 1.0, 0.0]);
+            add([x, 2.0, z], [0.0, -1.0, 0.0]);
+        }
+        let len = normals.len() / 3;
+        // Centered, so that the rotations are not correlated with the translations
+        let points = Array2::from_shape_vec((len, 3), points).unwrap() - ndarray::array![10.0, 1.0, 0.5];
+        let cloud = PointCloud::with_normals(points.into_raw_vec(), normals);
+        let view = cloud.view();
+
+        // Smallest eigenvalue of the normal matrix of the point-to-plane adjustment
+        let min_eigenvalue = |idx: &[usize]| {
+            let mut normal_matrix: Array2<f64> = Array2::zeros((6, 6));
+            for i in idx {
+                let row = design_row(view.point(*i), view.normal(*i));
+                normal_matrix += &Array2::from_shape_fn((6, 6), |(j, k)| row[j] * row[k]);
+            }
+            let (values, _) = normal_matrix.eigh_into().unwrap();
+            values.fold(f64::INFINITY, |a, b| a.min(*b))
+        };
+
+        // Stride sampling misses the end wall, i.e. the translation along the corridor
+        let stride = stride_indices(len, 60);
+        assert!(!stride.iter().any(|i| (1000..1010).contains(i)));
+        assert!(min_eigenvalue(&stride) < 1e-9);
+
+        let idx = stability_indices(&view, 60);
+        assert_eq!(idx.len(), 60);
+        assert!(idx.iter().any(|i| (1000..1010).contains(i)));
+        assert!(min_eigenvalue(&idx) > 1.0);
+    }
+
+    #[test]
+    fn spread_sampling_keeps_minimum_spacing() {
+        // Dense cluster of 900 points at the origin plus ten points along the x axis
+        let points = Array2::from_shape_fn((910, 3), |(i, axis)| {
+            match (i < 900, axis) {
+                (true, _) => ((i / 30 + axis * (i % 30)) % 30) as f64 * 1e-3,
+                (false, 0) => (i - 899) as f64,
+                _ => 0.0,
+            }
+        });
+
+        let cloud = PointCloud::new(points.clone().into_raw_vec());
+        let idx = farthest_point_indices(&cloud.view(), 11);
+        assert_eq!(idx.len(), 11);
+        assert_eq!(idx.iter().filter(|i| **i >= 900).count(), 10);
+
+        let idx = poisson_disk_indices(&cloud.view(), 0.5);
+        assert_eq!(idx.len(), 11);
+        for (a, b) in idx.iter().flat_map(|a| idx.iter().filter(move |b| a < *b).map(move |b| (a, b))) {
+            let sq_dist: f64 = (0..3).map(|axis| (points[[*a, axis]] - points[[*b, axis]]).powi(2)).sum();
+            assert!(sq_dist >= 0.25);
+        }
+    }
+}
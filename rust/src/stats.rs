@@ -0,0 +1,208 @@
+//! Robust statistics of samples.
+//!
+//! All estimators ignore NaN values and return NaN if no value is left. Medians and quantiles are
+//! found by selection (expected O(n)) instead of sorting.
+
+use std::cmp::Ordering;
+
+/// Factor which turns the MAD into a consistent estimator of the standard deviation of normally
+/// distributed values.
+pub const MAD_TO_SIGMA: f64 = 1.4826;
+
+const QN_TO_SIGMA: f64 = 2.2219;
+const SN_TO_SIGMA: f64 = 1.1926;
+
+fn finite<'a>(values: impl IntoIterator<Item=&'a f64>) -> Vec<f64> {
+    values.into_iter().copied().filter(|v| !v.is_nan()).collect()
+}
+
+// k-th smallest value (0-based), reorders values
+fn select(values: &mut [f64], k: usize) -> f64 {
+    *values.select_nth_unstable_by(k, f64::total_cmp).1
+}
+
+// Smallest value of a non-empty slice
+fn min(values: &[f64]) -> f64 {
+    values.iter().copied().fold(f64::INFINITY, f64::min)
+}
+
+fn median_in_place(values: &mut [f64]) -> f64 {
+    let len = values.len();
+    if len == 0 {
+        return f64::NAN;
+    }
+    let upper = select(values, len / 2);
+    if len % 2 == 1 {
+        upper
+    } else {
+        // After the selection, the lower middle element is the largest one left of len / 2
+        let lower = values[..len / 2].iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        (lower + upper) / 2.0
+    }
+}
+
+/// Median, i.e. the mean of the two middle values for an even number of values
+pub fn median<'a>(values: impl IntoIterator<Item=&'a f64>) -> f64 {
+    median_in_place(&mut finite(values))
+}
+
+/// Quantile q in [0, 1], linearly interpolated between the closest ranks
+pub fn quantile<'a>(values: impl IntoIterator<Item=&'a f64>, q: f64) -> f64 {
+    assert!((0.0..=1.0).contains(&q), "Quantile must be within [0, 1]");
+    let mut values = finite(values);
+    if values.is_empty() {
+        return f64::NAN;
+    }
+    let h = (values.len() - 1) as f64 * q;
+    let k = h.floor() as usize;
+    let lower = select(&mut values, k);
+    if k + 1 == values.len() {
+        return lower;
+    }
+    let upper = min(&values[k + 1..]);
+    lower + (h - k as f64) * (upper - lower)
+}
+
+/// Median absolute deviation from the median (not scaled, see MAD_TO_SIGMA)
+pub fn mad<'a>(values: impl IntoIterator<Item=&'a f64>) -> f64 {
+    let mut values = finite(values);
+    let med = median_in_place(&mut values);
+    values.iter_mut().for_each(|v| *v = (*v - med).abs());
+    median_in_place(&mut values)
+}
+
+/// Robust standard deviation, i.e. MAD_TO_SIGMA * MAD
+pub fn sigma_mad<'a>(values: impl IntoIterator<Item=&'a f64>) -> f64 {
+    MAD_TO_SIGMA * mad(values)
+}
+
+/// Median of values with non-negative weights, i.e. the smallest value for which the cumulated weight
+/// reaches half of the total weight (average of two values if it is reached exactly). Pairs with NaN
+/// value or without positive weight are ignored.
+pub fn weighted_median<'a>(values: impl IntoIterator<Item=&'a f64>, weights: impl IntoIterator<Item=&'a f64>) -> f64 {
+    let mut pairs: Vec<(f64, f64)> = values.into_iter().copied()
+        .zip(weights.into_iter().copied())
+        .filter(|(v, w)| !v.is_nan() && *w > 0.0)
+        .collect();
+    pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let half = pairs.iter().map(|(_, w)| w).sum::<f64>() / 2.0;
+
+    let mut cumulated = 0.0;
+    for (i, (v, w)) in pairs.iter().enumerate() {
+        cumulated += w;
+        match cumulated.partial_cmp(&half) {
+            Some(Ordering::Equal) if i + 1 < pairs.len() => return (v + pairs[i + 1].0) / 2.0,
+            Some(Ordering::Equal) | Some(Ordering::Greater) => return *v,
+            _ => {}
+        }
+    }
+    f64::NAN
+}
+
+/// Mean of the values after removing the fraction proportion (in [0, 0.5)) of the smallest and of the
+/// largest values each
+pub fn trimmed_mean<'a>(values: impl IntoIterator<Item=&'a f64>, proportion: f64) -> f64 {
+    assert!((0.0..0.5).contains(&proportion), "Trimmed proportion must be within [0, 0.5)");
+    let mut values = finite(values);
+    let len = values.len();
+    let k = (len as f64 * proportion).floor() as usize;
+    if len == 0 {
+        return f64::NAN;
+    }
+    if k > 0 {
+        select(&mut values, k);
+        let upper = &mut values[k..];
+        let kept = len - 2 * k;
+        select(upper, kept - 1);
+    }
+    let kept = &values[k..len - k];
+    kept.iter().sum::<f64>() / kept.len() as f64
+}
+
+/// Qn scale estimator of Rousseeuw and Croux (without small sample correction), i.e. the first quartile
+/// of the pairwise distances. It is as robust as the MAD, but more efficient and doesn't assume a
+/// symmetric distribution. Needs O(n^2) time and memory.
+pub fn qn<'a>(values: impl IntoIterator<Item=&'a f64>) -> f64 {
+    let values = finite(values);
+    let n = values.len();
+    if n < 2 {
+        return f64::NAN;
+    }
+    let mut dists: Vec<f64> = values.iter().enumerate()
+        .flat_map(|(i, x)| values[i + 1..].iter().map(move |y| (x - y).abs()))
+        .collect();
+    let h = n / 2 + 1;
+    let k = h * (h - 1) / 2;
+    QN_TO_SIGMA * select(&mut dists, k - 1)
+}
+
+/// Sn scale estimator of Rousseeuw and Croux (without small sample correction), i.e. the low median
+/// over all points of the high median of their distances to all points. Needs O(n^2) time, but only
+/// O(n) memory.
+pub fn sn<'a>(values: impl IntoIterator<Item=&'a f64>) -> f64 {
+    let values = finite(values);
+    let n = values.len();
+    if n < 2 {
+        return f64::NAN;
+    }
+    let mut dists = vec![0.0; n];
+    let mut medians: Vec<f64> = values.iter()
+        .map(|x| {
+            dists.iter_mut().zip(&values).for_each(|(d, y)| *d = (x - y).abs());
+            select(&mut dists, n / 2)
+        })
+        .collect();
+    SN_TO_SIGMA * select(&mut medians, n.div_ceil(2) - 1)
+}
+
+
+#[cfg(test)]
+mod stats_test {
+    use ndarray::{array, Array, Array1};
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::stats::{mad, median, qn, quantile, sigma_mad, sn, trimmed_mean, weighted_median};
+
+    #[test]
+    fn median_of_odd_and_even_lengths() {
+        let arr1: Array1<f64> = Array::linspace(0., 1.0, 9);
+        assert_eq!(median(&arr1), 0.5);
+        let arr2: Array1<f64> = Array::linspace(1., 8.0, 8);
+        assert_eq!(median(&arr2), 4.5);
+        let arr3: Array1<f64> = array![3.0, 0.0, 1.0]; // Test that values are sorted beforehand
+        assert_eq!(median(&arr3), 1.0);
+        assert_eq!(median(&[2.0, f64::NAN, 4.0, 1.0]), 2.0);
+        assert!(median(&[f64::NAN]).is_nan());
+    }
+
+    #[test]
+    fn quantiles_and_scale_estimators() {
+        let values = [7.0, 1.0, 3.0, f64::NAN, 5.0, 9.0, 100.0];
+        assert_eq!(quantile(&values, 0.0), 1.0);
+        assert_eq!(quantile(&values, 0.25), 3.5);
+        assert_eq!(quantile(&values, 1.0), 100.0);
+        assert_eq!(mad(&values), 3.0);
+        assert_eq!(trimmed_mean(&values, 0.2), 6.0);
+        assert_eq!(weighted_median(&values, &[1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 10.0]), 100.0);
+        assert_eq!(weighted_median(&[1.0, 2.0, 3.0, 4.0], &[1.0; 4]), 2.5);
+
+    }
+
+    #[test]
+    fn scale_estimators_ignore_outliers() {
+        // Normally distributed values with standard deviation 2 (Box-Muller) and 10% gross outliers
+        let mut rng = ChaCha8Rng::seed_from_u64(42);
+        let mut values: Vec<f64> = (0..500)
+            .map(|_| {
+                let (u1, u2): (f64, f64) = (rng.gen(), rng.gen());
+                2.0 * (-2.0 * (1.0 - u1).ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+            })
+            .collect();
+        values.extend((0..50).map(|i| 1000.0 + i as f64));
+
+        for sigma in [sigma_mad(&values), qn(&values), sn(&values)] {
+            assert!((sigma - 2.0).abs() < 0.4, "{}", sigma);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+
+use ndarray::ArrayView2;
+
+use crate::cloud_view::CloudView;
+use crate::pointcloud::PointCloud;
+
+/// Point which represents all points of an occupied voxel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VoxelRepresentative {
+    /// Centroid of the voxel; normals and attributes are averaged.
+    Centroid,
+    /// Original point nearest to the centroid; normals and attributes are copied.
+    NearestToCentroid,
+}
+
+pub struct VoxelDownsampling {
+    /// One point per occupied voxel.
+    pub cloud: PointCloud,
+    /// Original indices of all points within each voxel.
+    pub members: Vec<Vec<usize>>,
+    /// Original index of the point nearest to the centroid of each voxel.
+    pub nearest_idx: Vec<usize>,
+}
+
+// Groups the points by voxel (by their position in the view). Voxels are ordered by the first
+// point they contain.
+pub(crate) fn voxel_members(view: &CloudView, voxel_size: f64) -> Vec<Vec<usize>> {
+    assert!(voxel_size > 0.0, "voxel_size must be > 0");
+
+    let mut slots: HashMap<[i64; 3], usize> = HashMap::new();
+    let mut members: Vec<Vec<usize>> = Vec::new();
+    for (i, p) in view.points().enumerate() {
+        let key = [0, 1, 2].map(|axis| (p[axis] / voxel_size).floor() as i64);
+        let slot = *slots.entry(key).or_insert_with(|| {
+            members.push(Vec::new());
+            members.len() - 1
+        });
+        members[slot].push(i);
+    }
+    members
+}
+
+pub(crate) fn centroid(points: ArrayView2<f64>, idx: &[usize]) -> [f64; 3] {
+    let mut c = [0.0; 3];
+    for i in idx {
+        for (axis, coord) in c.iter_mut().enumerate() {
+            *coord += points[[*i, axis]];
+        }
+    }
+    c.map(|coord| coord / idx.len() as f64)
+}
+
+#[cfg(test)]
+mod voxel_grid_test {
+    use crate::pointcloud::PointCloud;
+    use crate::voxel_grid::VoxelRepresentative;
+
+    #[test]
+    fn voxel_downsample() {
+        let mut cloud = PointCloud::new(vec![
+            0.1, 0.1, 0.1,
+            5.5, 0.5, 0.5,
+            0.3, 0.3, 0.3,
+            0.4, 0.4, 0.2,
+        ]);
+
+        let res = cloud.voxel_downsample(1.0, VoxelRepresentative::Centroid);
+        assert_eq!(res.members, vec![vec![0, 2, 3], vec![1]]);
+        assert_eq!(res.nearest_idx, vec![2, 1]);
+        assert_float_absolute_eq!(res.cloud.points()[[0, 0]], 0.8 / 3.0, 1e-12);
+        assert_float_absolute_eq!(res.cloud.points()[[0, 2]], 0.2, 1e-12);
+
+        cloud.estimate_normals(3);
+        let res = cloud.voxel_downsample(1.0, VoxelRepresentative::NearestToCentroid);
+        assert_eq!(res.cloud.points()[[0, 0]], 0.3);
+        assert_eq!(res.cloud.normals().row(0), cloud.normals().row(2));
+    }
+}